@@ -0,0 +1,204 @@
+use std::collections::BTreeMap;
+
+use crate::data::BibEntry;
+
+// entries `@type{key, field = {value}, ...}`; comments, strings and preambles are skipped
+pub fn parse_bib(src: &str) -> BTreeMap<String, BibEntry> {
+    let mut entries = BTreeMap::new();
+    let mut chs = src;
+    while let Some(pos) = chs.find('@') {
+        chs = &chs[pos + 1..];
+        // an entry starts with `@kind{`, and other `@`s such as in email addresses are skipped
+        let name = chs.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(chs.len());
+        let open = name + chs[name..].len() - chs[name..].trim_start().len();
+        if name == 0 || !chs[open..].starts_with('{') {
+            continue;
+        }
+        let kind = chs[..name].to_lowercase();
+        let Some((body, rest)) = read_group(&chs[open..]) else {
+            break;
+        };
+        chs = rest;
+        if matches!(kind.as_str(), "comment" | "string" | "preamble") {
+            continue;
+        }
+
+        let (key, mut body) = body.split_once(',').unwrap_or((body, ""));
+        let mut fields = BTreeMap::new();
+        while let Some(eq) = body.find('=') {
+            let name = body[..eq].trim_matches(|c: char| c == ',' || c.is_whitespace()).to_lowercase();
+            let (value, rest) = read_value(body[eq + 1..].trim_start());
+            fields.insert(name, clean(value));
+            body = rest;
+        }
+        entries.insert(key.trim().to_string(), BibEntry { kind, fields });
+    }
+    entries
+}
+
+// the content of a brace group and the rest
+fn read_group(chs: &str) -> Option<(&str, &str)> {
+    let mut depth = 0;
+    for (i, c) in chs.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 1 => return Some((&chs[1..i], &chs[i + 1..])),
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+// `{value}`, `"value"` or a bare number
+fn read_value(chs: &str) -> (&str, &str) {
+    if let Some(rest) = chs.strip_prefix('{') {
+        return read_group(chs).unwrap_or((rest, ""));
+    }
+    if let Some(rest) = chs.strip_prefix('"') {
+        let mut depth = 0;
+        for (i, c) in rest.char_indices() {
+            match c {
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                '"' if depth == 0 => return (&rest[..i], &rest[i + 1..]),
+                _ => {}
+            }
+        }
+        return (rest, "");
+    }
+    let end = chs.find(',').unwrap_or(chs.len());
+    (chs[..end].trim(), &chs[end..])
+}
+
+fn clean(value: &str) -> String {
+    let value = value.replace(['{', '}'], "").replace("\\&", "&").replace('\\', "").replace("---", "—").replace("--", "–").replace('~', " ");
+    value.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// "Last, First" or "First Last"
+fn split_name(name: &str) -> (&str, String) {
+    match name.split_once(',') {
+        Some((last, first)) => (last.trim(), format!("{} {}", first.trim(), last.trim())),
+        None => (name.rsplit(' ').next().unwrap_or(name), name.to_string()),
+    }
+}
+
+fn authors(entry: &BibEntry) -> Vec<&str> {
+    let authors = entry.fields.get("author").or(entry.fields.get("editor"));
+    authors.map_or(Vec::new(), |authors| authors.split(" and ").map(str::trim).collect())
+}
+
+// the label of author-year citations such as "Knuth 1984" or "Knuth et al. 1984"
+pub fn label(entry: &BibEntry) -> String {
+    let names: Vec<&str> = authors(entry).into_iter().map(|name| split_name(name).0).collect();
+    let names = match names.as_slice() {
+        [] => entry.fields.get("title").map_or("Anonymous", String::as_str).to_string(),
+        [name] => name.to_string(),
+        [first, second] => format!("{first} and {second}"),
+        [first, ..] => format!("{first} et al."),
+    };
+    match entry.fields.get("year") {
+        Some(year) => format!("{names} {year}"),
+        None => names,
+    }
+}
+
+pub fn format(entry: &BibEntry, author_year: bool) -> String {
+    let field = |name: &str| entry.fields.get(name).map(|value| escape(value));
+
+    let names: Vec<String> = authors(entry).into_iter().map(|name| escape(&split_name(name).1)).collect();
+    let mut res = match names.as_slice() {
+        [] => String::new(),
+        [name] => name.clone(),
+        [names @ .., last] => format!("{} and {last}", names.join(", ")),
+    };
+    if author_year {
+        if let Some(year) = field("year") {
+            res.push_str(&format!(" ({year})"));
+        }
+    }
+    if !res.is_empty() {
+        res.push_str(". ");
+    }
+
+    let title = field("title").unwrap_or_default();
+    let venue = field("journal").or(field("booktitle"));
+    if venue.is_none() && entry.kind == "book" {
+        res.push_str(&format!("<em>{title}</em>."));
+    } else {
+        res.push_str(&format!("{title}."));
+    }
+
+    let mut details = Vec::new();
+    if let Some(venue) = venue {
+        let mut venue = format!("<em>{venue}</em>");
+        if let Some(volume) = field("volume") {
+            venue.push_str(&format!(", {volume}"));
+            if let Some(number) = field("number") {
+                venue.push_str(&format!("({number})"));
+            }
+        }
+        if let Some(pages) = field("pages") {
+            venue.push_str(&format!(", pp. {pages}"));
+        }
+        details.push(venue);
+    }
+    details.extend(field("publisher").or(field("school")).or(field("institution")));
+    if !author_year {
+        details.extend(field("year"));
+    }
+    if !details.is_empty() {
+        res.push_str(&format!(" {}.", details.join(", ")));
+    }
+
+    if let Some(doi) = field("doi") {
+        res.push_str(&format!(" <a href=\"https://doi.org/{doi}\">doi:{doi}</a>"));
+    } else if let Some(url) = field("url") {
+        res.push_str(&format!(" <a href=\"{url}\">{url}</a>"));
+    }
+    res
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values() {
+        let src = "@Article{knuth84,\n  Author = {Knuth, Donald E.},\n  title = \"The {\\TeX}book --- {A} \\& B\",\n  year = 1984,\n  pages = {1--10},\n  note = {nested {braces} and~ties}\n}\n";
+        let entries = parse_bib(src);
+        let entry = &entries["knuth84"];
+        assert_eq!(entry.kind, "article");
+        assert_eq!(entry.fields["author"], "Knuth, Donald E.");
+        assert_eq!(entry.fields["title"], "The TeXbook — A & B");
+        assert_eq!(entry.fields["year"], "1984");
+        assert_eq!(entry.fields["pages"], "1–10");
+        assert_eq!(entry.fields["note"], "nested braces and ties");
+    }
+
+    #[test]
+    fn text_outside_entries() {
+        let src = "% ask me@example.com\n@comment{ignored, x = 1}\n@string{foo = \"bar\"}\nsee @ here\n@misc {k,\n  title = {T}\n}\n";
+        let entries = parse_bib(src);
+        assert_eq!(entries.keys().collect::<Vec<_>>(), ["k"]);
+        assert_eq!(entries["k"].kind, "misc");
+    }
+
+    #[test]
+    fn labels_and_references() {
+        let entries = parse_bib(
+            "@book{a, author = {Donald Knuth and Patashnik, Oren}, title = {Concrete}, publisher = {AW}, year = {1994}}\n\
+             @inproceedings{b, author = {A and B and C}, title = {P}, booktitle = {Conf}, doi = {10.1/x}}\n",
+        );
+        assert_eq!(label(&entries["a"]), "Knuth and Patashnik 1994");
+        assert_eq!(label(&entries["b"]), "A et al.");
+        assert_eq!(format(&entries["a"], false), "Donald Knuth and Oren Patashnik. <em>Concrete</em>. AW, 1994.");
+        assert_eq!(format(&entries["a"], true), "Donald Knuth and Oren Patashnik (1994). <em>Concrete</em>. AW.");
+        assert_eq!(format(&entries["b"], false), "A, B and C. P. <em>Conf</em>. <a href=\"https://doi.org/10.1/x\">doi:10.1/x</a>");
+    }
+}
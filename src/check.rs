@@ -0,0 +1,223 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::data::*;
+use crate::external::{ExternalChecker, Status};
+use crate::parser::{parse, Includes};
+use crate::site::{find_documents, resolve_wiki_links, Page};
+use Block::*;
+use Span::*;
+
+// the documents or directories of documents to check, and whether every reference is valid
+pub fn check(paths: &[String], options: &[(&str, &str)]) -> bool {
+    // the files with their paths in a site built from the directory
+    let mut files = Vec::new();
+    for path in paths.iter().map(Path::new) {
+        if !path.is_dir() {
+            files.push((path.to_path_buf(), path.file_name().map_or(PathBuf::new(), PathBuf::from).with_extension("html")));
+            continue;
+        }
+        let mut found = Vec::new();
+        if find_documents(path, &mut found).is_err() {
+            println!("could not read the directory \"{}\".", path.display());
+            return false;
+        }
+        files.extend(found.into_iter().map(|file| {
+            let out = file.strip_prefix(path).unwrap_or(&file).with_extension("html");
+            (file, out)
+        }));
+    }
+
+    let mut sources = Vec::new();
+    for (file, out) in files {
+        let Ok(src) = fs::read_to_string(&file) else {
+            println!("could not open the source file \"{}\".", file.display());
+            return false;
+        };
+        let includes = Includes::read(&file, &src);
+        sources.push((file, out, src, includes));
+    }
+
+    // the pages are not requested while parsing, and only `--external` sends requests
+    let mut parse_options = options.to_vec();
+    parse_options.push(("fetch", "false"));
+    let mut pages = Vec::new();
+    let mut ok = true;
+    for (file, out, src, includes) in &sources {
+        match parse(src, file, includes, &parse_options) {
+            Ok(doc) => pages.push(Page { src: file.clone(), out: out.clone(), doc }),
+            Err(err) => {
+                println!("Syntax Error: {}: {err}", file.display());
+                ok = false;
+            }
+        }
+    }
+
+    let mut broken = 0;
+    for (i, link) in resolve_wiki_links(&mut pages) {
+        println!("{}: missing wiki link target \"{link}\"", pages[i].src.display());
+        broken += 1;
+    }
+
+    // the anchors of every document by its canonical path
+    let mut anchors = BTreeMap::new();
+    let mut refs = Vec::new();
+    for page in pages.iter_mut() {
+        let mut ids = BTreeSet::new();
+        let mut urls = Vec::new();
+        collect_refs(&mut page.doc.content, &mut ids, &mut urls);
+        anchors.insert(fs::canonicalize(&page.src).unwrap_or(page.src.clone()), ids);
+        refs.push((page.src.as_path(), urls));
+    }
+
+    for (file, urls) in &refs {
+        for url in urls {
+            if let Err(reason) = check_url(file, url, &anchors) {
+                println!("{}: {reason} \"{url}\"", file.display());
+                broken += 1;
+            }
+        }
+    }
+
+    // `--external` also requests every external URL once
+    if options.contains(&("external", "true")) {
+        let checker = match ExternalChecker::new(options) {
+            Ok(checker) => checker,
+            Err(err) => {
+                println!("could not check external links: {err}");
+                return false;
+            }
+        };
+        let mut external: BTreeMap<&str, Vec<&Path>> = BTreeMap::new();
+        for (file, urls) in &refs {
+            for url in urls.iter().filter(|url| checker.is_target(url)) {
+                external.entry(url).or_default().push(file);
+            }
+        }
+        let urls = external.keys().map(|url| url.to_string()).collect();
+        for (url, status) in checker.check(urls) {
+            let mut files = external[url.as_str()].clone();
+            files.dedup();
+            for file in files {
+                match &status {
+                    Status::Ok => {}
+                    Status::Redirect(code, location) => println!("Warning: {}: \"{url}\" redirects to \"{location}\" ({code})", file.display()),
+                    Status::Broken(reason) => {
+                        println!("{}: {reason} \"{url}\"", file.display());
+                        broken += 1;
+                    }
+                }
+            }
+        }
+    }
+    if broken > 0 {
+        println!("{broken} broken reference{}.", if broken == 1 { "" } else { "s" });
+    }
+    ok && broken == 0
+}
+
+// the IDs of the generated HTML and the URLs of links, images and embedded media
+fn collect_refs(blocks: &mut [Block], ids: &mut BTreeSet<String>, urls: &mut Vec<String>) {
+    for block in blocks.iter_mut() {
+        match block {
+            Header { id, .. } => {
+                ids.insert(id.clone());
+            }
+            Media { url, caption, .. } => {
+                urls.push(url.to_string());
+                ids.extend(caption.id.map(str::to_string));
+            }
+            Table { caption: Some(caption), .. } | CodeBlock { caption: Some(caption), .. } => {
+                ids.extend(caption.id.map(str::to_string));
+            }
+            LinkCard { url, .. } => urls.push(url.to_string()),
+            MathBlock { id, .. } => {
+                ids.insert(id.clone());
+            }
+            Env { title, id, content, .. } => {
+                ids.extend(id.map(str::to_string));
+                if let Some(title) = title {
+                    let _ = title.walk_spans_mut(&mut |span| span_refs(span, ids, urls));
+                }
+                collect_refs(content, ids, urls);
+                continue;
+            }
+            Ref(notes, bib) => {
+                ids.extend(notes.iter().map(|(_, id, _)| format!("ref-{id}")));
+                ids.extend(bib.iter().map(|(key, _, _)| format!("bib-{key}")));
+            }
+            _ => {}
+        }
+
+        let _ = block.walk_spans_mut(&mut |span| span_refs(span, ids, urls));
+    }
+}
+
+fn span_refs(span: &mut Span, ids: &mut BTreeSet<String>, urls: &mut Vec<String>) -> Result<(), std::convert::Infallible> {
+    match span {
+        Link { url, .. } => urls.push(url.to_string()),
+        Image { url, .. } => urls.push(url.to_string()),
+        Cite { id, count, .. } => {
+            ids.insert(cite_id(*id, *count));
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+// a fragment of the same document, or a relative path from the document with an optional fragment
+fn check_url(file: &Path, url: &str, anchors: &BTreeMap<PathBuf, BTreeSet<String>>) -> Result<(), &'static str> {
+    let (path, fragment) = url.split_once('#').map_or((url, None), |(path, fragment)| (path, Some(fragment)));
+    let path = &path[..path.find('?').unwrap_or(path.len())];
+    if path.starts_with('/') || path.contains(':') || path.starts_with("www.") {
+        return Ok(());
+    }
+
+    let target = if path.is_empty() {
+        file.to_path_buf()
+    } else {
+        let target = file.parent().unwrap_or(Path::new("")).join(path);
+        // links to the output of a document refer to the document
+        let source = target.with_extension("md");
+        if target.extension().is_some_and(|ext| ext == "html") && !target.exists() && source.exists() {
+            source
+        } else if target.exists() {
+            target
+        } else {
+            return Err("missing file");
+        }
+    };
+
+    let Some(fragment) = fragment.filter(|fragment| !fragment.is_empty()) else {
+        return Ok(());
+    };
+    let canonical = fs::canonicalize(&target).unwrap_or(target);
+    match anchors.get(&canonical) {
+        Some(ids) if ids.contains(fragment) || ids.contains(&percent_decode(fragment)) => Ok(()),
+        Some(_) => Err("missing anchor"),
+        // fragments of files other than the checked documents are not known
+        None => Ok(()),
+    }
+}
+
+// `%E6%97%A5` as the UTF-8 bytes it encodes
+fn percent_decode(text: &str) -> String {
+    let mut bytes = Vec::new();
+    let mut rest = text.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        let hex = tail.get(..2).filter(|hex| hex.iter().all(u8::is_ascii_hexdigit));
+        let hex = hex.and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match hex {
+            Some(byte) if b == b'%' => {
+                bytes.push(byte);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(b);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
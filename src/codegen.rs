@@ -2,26 +2,35 @@ use std::io::{self, Write};
 use std::fs::File;
 use chrono::{Local, Datelike, Timelike};
 
+use crate::bib;
 use crate::data::*;
+use crate::highlight::{split_lines, tokenize, Tok};
 
 use Block::*;
 use Elem::*;
 
-pub fn gen_html(dest: &mut File, file: &str, title: &str, toc: &List, content: &Vec<Block>, template: &Vec<Elem>) -> Result<(), io::Error> {
+pub fn gen_html(dest: &mut File, file: &str, doc: &Document, template: &Vec<Elem>) -> Result<(), io::Error> {
     let mut codegen = CodeGen::new(dest);
-    codegen.gen_html(file, title, toc, content, template)
+    codegen.gen_html(file, doc, template)
 }
 
 struct CodeGen<'a> {
     dest: &'a mut File,
+    highlight: bool,
+    author_year: bool,
+    sidenotes: bool,
 }
 
 impl<'a> CodeGen<'a> {
     fn new(dest: &'a mut File) -> Self {
-        CodeGen { dest }
+        CodeGen { dest, highlight: false, author_year: false, sidenotes: false }
     }
 
-    fn gen_html(&mut self, file: &str, title: &str, toc: &List, content: &Vec<Block>, template: &Vec<Elem>) -> Result<(), io::Error> {
+    fn gen_html(&mut self, file: &str, doc: &Document, template: &Vec<Elem>) -> Result<(), io::Error> {
+        let Document { title, toc, content, macros, meta, backlinks } = doc;
+        self.highlight = meta.get("highlight") == Some(&"true");
+        self.author_year = meta.get("citation-style") == Some(&"author-year");
+        self.sidenotes = meta.get("footnotes") == Some(&"sidenote");
         let datetime = Local::now();
         for chunk in template {
             match chunk {
@@ -35,6 +44,8 @@ impl<'a> CodeGen<'a> {
                 Second => write!(self.dest, "{:02}", datetime.second())?,
                 Toc(indent) => self.gen_toc(toc, *indent)?,
                 Content(indent) => self.gen_content(content, *indent)?,
+                Macros(indent) => self.gen_macros(macros, *indent)?,
+                Backlinks(indent) => self.gen_backlinks(backlinks, *indent)?,
                 Str(text) => write!(self.dest, "{text}")?,
             }
         }
@@ -43,29 +54,67 @@ impl<'a> CodeGen<'a> {
 
     fn gen_toc(&mut self, toc: &List, indent: usize) -> Result<(), io::Error> {
         writeln!(self.dest)?;
-        self.gen_list(&toc, indent)
+        self.gen_list(toc, indent)
     }
 
     fn gen_content(&mut self, content: &Vec<Block>, indent: usize) -> Result<(), io::Error> {
         writeln!(self.dest)?;
+        self.gen_blocks(content, indent)
+    }
+
+    fn gen_blocks(&mut self, content: &Vec<Block>, indent: usize) -> Result<(), io::Error> {
         for block in content {
             match block {
                 Header { header, level, id } => self.gen_header(header, level, id, indent)?,
                 Blockquote { lines } => self.gen_blockquote(lines, indent)?,
                 ListBlock(list) => self.gen_list(list, indent)?,
-                Table { head, body } => self.gen_table(head, body, indent)?,
-                Image { title, url } => self.gen_image(title, url, indent)?,
-                Video { title, url } => self.gen_video(title, url, indent)?,
+                Table { head, body, caption } => self.gen_table(head, body, caption, indent)?,
+                Media { kind, url, mime, attrs, caption } => self.gen_media(kind, url, mime, attrs, caption, indent)?,
                 LinkCard { title, image, url, description, site_name } => self.gen_link_card(title, image, url, description, site_name, indent)?,
-                MathBlock { math } => self.gen_math_block(math, indent)?,
-                CodeBlock { lang, code } => self.gen_code_block(lang, code, indent)?,
+                MathBlock { math, mathml, number, id } => self.gen_math_block(math, mathml, number, id, indent)?,
+                CodeBlock { lang, code, attrs, caption } => self.gen_code_block(lang, code, attrs, caption, indent)?,
                 Paragraph { text } => self.gen_paragraph(text, indent)?,
-                Ref(notes) => self.gen_ref(notes, indent)?,
+                Env { kind, title, number, id, content } => self.gen_env(kind, title, number, id, content, indent)?,
+                Ref(notes, bib) => self.gen_ref(notes, bib, indent)?,
             }
         }
         Ok(())
     }
 
+    fn gen_backlinks(&mut self, backlinks: &Vec<(String, String)>, indent: usize) -> Result<(), io::Error> {
+        if backlinks.is_empty() {
+            return Ok(());
+        }
+        let indent = " ".repeat(indent);
+        writeln!(self.dest, "<ul class=\"backlinks\">")?;
+        for (title, url) in backlinks {
+            writeln!(self.dest, "{indent}  <li><a href=\"{}\">{}</a></li>", AttrText(url), AttrText(title))?;
+        }
+        writeln!(self.dest, "{indent}</ul>")
+    }
+
+    // merged into the MathJax configuration the template may already define
+    fn gen_macros(&mut self, macros: &Vec<Macro>, indent: usize) -> Result<(), io::Error> {
+        if macros.is_empty() {
+            return Ok(());
+        }
+        let indent = " ".repeat(indent);
+        writeln!(self.dest, "<script>")?;
+        writeln!(self.dest, "{indent}  window.MathJax = window.MathJax || {{}};")?;
+        writeln!(self.dest, "{indent}  MathJax.tex = MathJax.tex || {{}};")?;
+        writeln!(self.dest, "{indent}  MathJax.tex.macros = Object.assign(MathJax.tex.macros || {{}}, {{")?;
+        for Macro { name, args, body } in macros {
+            let body = body.replace('\\', "\\\\").replace('"', "\\\"");
+            if *args == 0 {
+                writeln!(self.dest, "{indent}    {name}: \"{body}\",")?;
+            } else {
+                writeln!(self.dest, "{indent}    {name}: [\"{body}\", {args}],")?;
+            }
+        }
+        writeln!(self.dest, "{indent}  }});")?;
+        write!(self.dest, "{indent}</script>")
+    }
+
     fn gen_header(&mut self, header: &Inline, level: &u32, id: &String, indent: usize) -> Result<(), io::Error> {
         let indent = " ".repeat(indent);
         writeln!(self.dest, "{indent}<h{level} id=\"{id}\">{header}</h{level}>")
@@ -98,20 +147,29 @@ impl<'a> CodeGen<'a> {
         writeln!(self.dest, "{indent}</{}>", if list.ordered { "ol" } else { "ul" })
     }
 
-    fn gen_image(&mut self, title: &Inline, url: &str, indent: usize) -> Result<(), io::Error> {
+    fn gen_media(&mut self, kind: &MediaKind, url: &str, mime: &str, attrs: &MediaAttrs, caption: &Caption, indent: usize) -> Result<(), io::Error> {
         let indent = " ".repeat(indent);
-        writeln!(self.dest, "{indent}<div class=\"image\">")?;
-        writeln!(self.dest, "{indent}  <img src=\"{url}\">")?;
-        writeln!(self.dest, "{indent}  <p class=\"caption\">{title}</p>")?;
-        writeln!(self.dest, "{indent}</div>")
-    }
-
-    fn gen_video(&mut self, title: &Inline, url: &str, indent: usize) -> Result<(), io::Error> {
-        let indent = " ".repeat(indent);
-        writeln!(self.dest, "{indent}<div class=\"video\">")?;
-        writeln!(self.dest, "{indent}  <video controls src=\"{url}\"></video>")?;
-        writeln!(self.dest, "{indent}  <p class=\"caption\">{title}</p>")?;
-        writeln!(self.dest, "{indent}</div>")
+        let (alt, loading) = (AttrText(&attrs.alt), attrs.loading);
+        let mut class = kind.class().to_string();
+        for name in &attrs.classes {
+            class.push(' ');
+            class.push_str(name);
+        }
+        writeln!(self.dest, "{indent}<figure class=\"{class}\"{}>", IdAttr(caption.id))?;
+        match kind {
+            MediaKind::Image => writeln!(self.dest, "{indent}  <img src=\"{url}\" alt=\"{alt}\"{attrs} loading=\"{loading}\">")?,
+            MediaKind::Video => writeln!(self.dest, "{indent}  <video controls{attrs}><source src=\"{url}\" type=\"{mime}\"></video>")?,
+            MediaKind::Audio => writeln!(self.dest, "{indent}  <audio controls><source src=\"{url}\" type=\"{mime}\"></audio>")?,
+            MediaKind::Frame => writeln!(self.dest, "{indent}  <iframe src=\"{url}\" title=\"{alt}\"{attrs} loading=\"{loading}\" allowfullscreen></iframe>")?,
+            MediaKind::Document => {
+                let text = AttrText(if attrs.alt.is_empty() { url } else { &attrs.alt });
+                writeln!(self.dest, "{indent}  <object data=\"{url}\" type=\"{mime}\"{attrs}><a href=\"{url}\">{text}</a></object>")?
+            }
+        }
+        if caption.number.is_some() {
+            writeln!(self.dest, "{indent}  <figcaption>{caption}</figcaption>")?;
+        }
+        writeln!(self.dest, "{indent}</figure>")
     }
 
     fn gen_link_card(&mut self, title: &String, image: &Option<String>, url: &str, description: &Option<String>, site_name: &Option<String>, indent: usize) -> Result<(), io::Error> {
@@ -131,43 +189,113 @@ impl<'a> CodeGen<'a> {
         writeln!(self.dest, "{indent}</a></div>")
     }
 
-    fn gen_table(&mut self, head: &Vec<Vec<Inline>>, body: &Vec<Vec<Inline>>, indent: usize) -> Result<(), io::Error> {
+    fn gen_table(&mut self, head: &Vec<Vec<Cell>>, body: &Vec<Vec<Cell>>, caption: &Option<Caption>, indent: usize) -> Result<(), io::Error> {
         let indent = " ".repeat(indent);
 
-        writeln!(self.dest, "{indent}<table>")?;
+        writeln!(self.dest, "{indent}<table{}>", IdAttr(caption.as_ref().and_then(|caption| caption.id)))?;
+        if let Some(caption) = caption {
+            writeln!(self.dest, "{indent}  <caption>{caption}</caption>")?;
+        }
 
-        writeln!(self.dest, "{indent}  <thead>")?;
+        if !head.is_empty() {
+            writeln!(self.dest, "{indent}  <thead>")?;
+        }
         for row in head {
             writeln!(self.dest, "{indent}    <tr>")?;
-            for data in row {
-                writeln!(self.dest, "{indent}      <td>{data}</td>")?;
+            for cell in row {
+                writeln!(self.dest, "{indent}      <th{cell}>{}</th>", cell.text)?;
             }
             writeln!(self.dest, "{indent}    </tr>")?;
         }
-        writeln!(self.dest, "{indent}  </thead>")?;
+        if !head.is_empty() {
+            writeln!(self.dest, "{indent}  </thead>")?;
+        }
         
-        writeln!(self.dest, "{indent}  <tbody>")?;
+        if !body.is_empty() {
+            writeln!(self.dest, "{indent}  <tbody>")?;
+        }
         for row in body {
             writeln!(self.dest, "{indent}    <tr>")?;
-            for data in row {
-                writeln!(self.dest, "{indent}      <td>{data}</td>")?;
+            for cell in row {
+                writeln!(self.dest, "{indent}      <td{cell}>{}</td>", cell.text)?;
             }
             writeln!(self.dest, "{indent}    </tr>")?;
         }
-        writeln!(self.dest, "{indent}  </tbody>")?;
+        if !body.is_empty() {
+            writeln!(self.dest, "{indent}  </tbody>")?;
+        }
         
         writeln!(self.dest, "{indent}</table>")
     }
 
-    fn gen_math_block(&mut self, math: &str, indent: usize) -> Result<(), io::Error> {
+    fn gen_math_block(&mut self, math: &str, mathml: &Option<String>, number: &usize, id: &String, indent: usize) -> Result<(), io::Error> {
         let indent = " ".repeat(indent);
-        writeln!(self.dest, "{indent}<p>\\[{}\\]</p>", MathText(math))
+        writeln!(self.dest, "{indent}<div class=\"equation\" id=\"{id}\">")?;
+        match mathml {
+            Some(mathml) => writeln!(self.dest, "{indent}  <p>{mathml}</p>")?,
+            None => writeln!(self.dest, "{indent}  <p>\\[{}\\]</p>", MathText(math))?,
+        }
+        writeln!(self.dest, "{indent}  <span class=\"eq-number\">({number})</span>")?;
+        writeln!(self.dest, "{indent}</div>")
     }
 
-    fn gen_code_block(&mut self, lang: &str, code: &str, indent: usize) -> Result<(), io::Error> {
-        let indent = " ".repeat(indent);
-        let lang = if lang == "" { "plaintext" } else { lang };
-        writeln!(self.dest, "{indent}<pre><code class=\"language-{lang}\">{}</code></pre>", HtmlText(code))
+    fn gen_code_block(&mut self, lang: &str, code: &str, attrs: &CodeAttrs, caption: &Option<Caption>, indent: usize) -> Result<(), io::Error> {
+        let lang = if lang.is_empty() { "plaintext" } else { lang };
+        let Some(caption) = caption else {
+            return self.gen_code(lang, code, attrs, indent);
+        };
+
+        let indent_str = " ".repeat(indent);
+        writeln!(self.dest, "{indent_str}<figure class=\"listing\"{}>", IdAttr(caption.id))?;
+        writeln!(self.dest, "{indent_str}  <figcaption>{caption}</figcaption>")?;
+        self.gen_code(lang, code, attrs, indent + 2)?;
+        writeln!(self.dest, "{indent_str}</figure>")
+    }
+
+    fn gen_code(&mut self, lang: &str, code: &str, attrs: &CodeAttrs, indent: usize) -> Result<(), io::Error> {
+        let mut indent = " ".repeat(indent);
+        if let Some(title) = attrs.title {
+            writeln!(self.dest, "{indent}<div class=\"code-block\">")?;
+            writeln!(self.dest, "{indent}  <div class=\"code-title\">{}</div>", HtmlText(title))?;
+            indent.push_str("  ");
+        }
+
+        let tokens = if self.highlight { tokenize(lang, code) } else { None };
+        let class = if tokens.is_some() { " highlight" } else { "" };
+        let tokens = tokens.unwrap_or_else(|| vec![(Tok::Plain, code)]);
+
+        write!(self.dest, "{indent}<pre><code class=\"language-{lang}{class}\">")?;
+        if attrs.linenos.is_none() && attrs.hl_lines.is_empty() {
+            self.gen_tokens(&tokens)?;
+        } else {
+            let start = attrs.linenos.unwrap_or(1);
+            for (i, line) in split_lines(tokens).iter().enumerate() {
+                let number = start + i;
+                let marked = attrs.hl_lines.iter().any(|&(first, last)| first <= number && number <= last);
+                write!(self.dest, "<span class=\"line{}\">", if marked { " hl" } else { "" })?;
+                if attrs.linenos.is_some() {
+                    write!(self.dest, "<span class=\"lineno\">{number}</span>")?;
+                }
+                self.gen_tokens(line)?;
+                writeln!(self.dest, "</span>")?;
+            }
+        }
+        writeln!(self.dest, "</code></pre>")?;
+
+        if attrs.title.is_some() {
+            writeln!(self.dest, "{}</div>", &indent[2..])?;
+        }
+        Ok(())
+    }
+
+    fn gen_tokens(&mut self, tokens: &Vec<(Tok, &str)>) -> Result<(), io::Error> {
+        for (tok, text) in tokens {
+            match tok.class() {
+                Some(class) => write!(self.dest, "<span class=\"{class}\">{}</span>", HtmlText(text))?,
+                None => write!(self.dest, "{}", HtmlText(text))?,
+            }
+        }
+        Ok(())
     }
 
     fn gen_paragraph(&mut self, text: &Inline, indent: usize) -> Result<(), io::Error> {
@@ -175,17 +303,67 @@ impl<'a> CodeGen<'a> {
         writeln!(self.dest, "{indent}<p>{text}</p>")
     }
 
-    fn gen_ref(&mut self, notes: &Vec<(Inline, usize)>, indent: usize) -> Result<(), io::Error> {
-        if notes.is_empty() {
+    fn gen_env(&mut self, kind: &EnvKind, title: &Option<Inline>, number: &Option<String>, id: &Option<&str>, content: &Vec<Block>, indent: usize) -> Result<(), io::Error> {
+        let indent_str = " ".repeat(indent);
+        writeln!(self.dest, "{indent_str}<div class=\"env {}\"{}>", kind.class(), IdAttr(*id))?;
+
+        write!(self.dest, "{indent_str}  <p class=\"env-title\"><strong>{}", kind.name())?;
+        if let Some(number) = number {
+            write!(self.dest, " {number}")?;
+        }
+        write!(self.dest, "</strong>")?;
+        if let Some(title) = title {
+            write!(self.dest, " ({title})")?;
+        }
+        writeln!(self.dest, "</p>")?;
+
+        self.gen_blocks(content, indent + 2)?;
+        if *kind == EnvKind::Proof {
+            writeln!(self.dest, "{indent_str}  <p class=\"qed\">&#8718;</p>")?;
+        }
+        writeln!(self.dest, "{indent_str}</div>")
+    }
+
+    fn gen_ref(&mut self, notes: &Vec<(Inline, usize, usize)>, bib: &Vec<(&str, String, BibEntry)>, indent: usize) -> Result<(), io::Error> {
+        if notes.is_empty() && bib.is_empty() {
             return Ok(());
         }
 
         let indent = " ".repeat(indent);
 
-        writeln!(self.dest, "{indent}<div class=\"ref\">")?;
-        for (note, id) in notes {
-            writeln!(self.dest, "{indent}  <p id=\"ref-{id}\"><a href=\"#cite-{id}\">[{id}]</a> {note}</p>")?;
+        if !notes.is_empty() {
+            // with sidenotes the end notes are only shown on narrow screens
+            let class = if self.sidenotes { "ref sidenotes" } else { "ref" };
+            writeln!(self.dest, "{indent}<div class=\"{class}\">")?;
+            for (note, id, cites) in notes {
+                if *cites <= 1 {
+                    writeln!(self.dest, "{indent}  <p id=\"ref-{id}\"><a href=\"#cite-{id}\">[{id}]</a> {note}</p>")?;
+                    continue;
+                }
+
+                // a note cited more than once links back to each citation
+                write!(self.dest, "{indent}  <p id=\"ref-{id}\">[{id}]")?;
+                for count in 1..=*cites {
+                    let mark = if count <= 26 { ((b'a' + count as u8 - 1) as char).to_string() } else { count.to_string() };
+                    write!(self.dest, " <a class=\"backlink\" href=\"#{}\">{mark}</a>", cite_id(*id, count))?;
+                }
+                writeln!(self.dest, " {note}</p>")?;
+            }
+            writeln!(self.dest, "{indent}</div>")?;
         }
-        writeln!(self.dest, "{indent}</div>")
+
+        if !bib.is_empty() {
+            writeln!(self.dest, "{indent}<div class=\"bibliography\">")?;
+            for (key, label, entry) in bib {
+                let entry = bib::format(entry, self.author_year);
+                if self.author_year {
+                    writeln!(self.dest, "{indent}  <p id=\"bib-{key}\">{entry}</p>")?;
+                } else {
+                    writeln!(self.dest, "{indent}  <p id=\"bib-{key}\">[{label}] {entry}</p>")?;
+                }
+            }
+            writeln!(self.dest, "{indent}</div>")?;
+        }
+        Ok(())
     }
 }
\ No newline at end of file
@@ -0,0 +1,103 @@
+// records of comma or tab separated values; quoted fields may contain separators, newlines and `""`
+pub fn read_csv(src: &str, sep: char) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chs = src.chars().peekable();
+    while let Some(c) = chs.next() {
+        match c {
+            '"' if quoted && chs.peek() == Some(&'"') => {
+                chs.next();
+                field.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            c if quoted => field.push(c),
+            c if c == sep => record.push(std::mem::take(&mut field)),
+            '\r' => {}
+            '\n' => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            c => field.push(c),
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    records.retain(|record| record.iter().any(|field| !field.trim().is_empty()));
+    records
+}
+
+pub fn is_number(field: &str) -> bool {
+    field.trim().parse::<f64>().is_ok()
+}
+
+// a number with a fixed number of decimals and digits grouped by `thousands`
+pub fn format_number(field: &str, decimals: Option<usize>, thousands: Option<&str>) -> String {
+    let field = field.trim();
+    let Ok(value) = field.parse::<f64>() else {
+        return field.to_string();
+    };
+    let number = match decimals {
+        Some(decimals) => format!("{value:.decimals$}"),
+        None => field.to_string(),
+    };
+    let Some(thousands) = thousands else {
+        return number;
+    };
+
+    let (sign, number) = number.strip_prefix('-').map_or(("", number.as_str()), |number| ("-", number));
+    let (int, frac) = number.split_once('.').map_or((number, None), |(int, frac)| (int, Some(frac)));
+    if !int.chars().all(|c| c.is_ascii_digit()) {
+        return format!("{sign}{number}");
+    }
+    let mut res = sign.to_string();
+    for (i, digit) in int.chars().enumerate() {
+        if i > 0 && (int.len() - i) % 3 == 0 {
+            res.push_str(thousands);
+        }
+        res.push(digit);
+    }
+    if let Some(frac) = frac {
+        res.push('.');
+        res.push_str(frac);
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quoted_fields() {
+        let src = "name,note\r\n\"Smith, J.\",\"said \"\"hi\"\"\nand left\"\n,\n\"\",x\nlast,\"unclosed";
+        assert_eq!(
+            read_csv(src, ','),
+            [
+                vec!["name", "note"],
+                vec!["Smith, J.", "said \"hi\"\nand left"],
+                vec!["", "x"],
+                vec!["last", "unclosed"],
+            ]
+        );
+    }
+
+    #[test]
+    fn quotes_inside_fields_and_tabs() {
+        assert_eq!(read_csv("5\" disk,a\"b\n", ','), [vec!["5\" disk", "a\"b"]]);
+        assert_eq!(read_csv("a,b\t\"c\td\"\n", '\t'), [vec!["a,b", "c\td"]]);
+    }
+
+    #[test]
+    fn numbers() {
+        assert!(is_number(" -1.5e3 ") && !is_number("1,000") && !is_number(""));
+        assert_eq!(format_number("1234567.891", Some(2), Some(",")), "1,234,567.89");
+        assert_eq!(format_number("-1234", None, Some(" ")), "-1 234");
+        assert_eq!(format_number("0.5", Some(0), None), "0");
+        assert_eq!(format_number("n/a", Some(2), Some(",")), "n/a");
+    }
+}
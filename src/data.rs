@@ -1,20 +1,102 @@
 use std::borrow::Cow;
+use std::collections::BTreeMap;
+use Block::*;
 use Span::*;
 use SyntaxError::*;
 
+#[derive(Debug)]
+pub struct Document<'a> {
+    pub title: String,
+    pub toc: List<'a>,
+    pub content: Vec<Block<'a>>,
+    pub macros: Vec<Macro>,
+    pub meta: BTreeMap<&'a str, &'a str>,
+    pub backlinks: Vec<(String, String)>,
+}
+
+#[derive(Clone, Debug)]
+pub struct BibEntry {
+    pub kind: String,
+    pub fields: BTreeMap<String, String>,
+}
+
+#[derive(Debug)]
+pub struct Macro {
+    pub name: String,
+    pub args: usize,
+    pub body: String,
+}
+
 #[derive(Debug)]
 pub enum Block<'a> {
     Header { header: Inline<'a>, level: u32, id: String },
     Blockquote { lines: Vec<Inline<'a>> },
     ListBlock(List<'a>),
-    Image { title: Inline<'a>, url: &'a str },
-    Video { title: Inline<'a>, url: &'a str },
+    Media { kind: MediaKind, url: Cow<'a, str>, mime: &'static str, attrs: MediaAttrs<'a>, caption: Caption<'a> },
     LinkCard { title: String, image: Option<String>, url: &'a str, description: Option<String>, site_name: Option<String> },
-    Table { head: Vec<Vec<Inline<'a>>>, body: Vec<Vec<Inline<'a>>> },
-    MathBlock { math: &'a str },
-    CodeBlock { lang: &'a str, code: &'a str },
+    Table { head: Vec<Vec<Cell<'a>>>, body: Vec<Vec<Cell<'a>>>, caption: Option<Caption<'a>> },
+    MathBlock { math: Cow<'a, str>, mathml: Option<String>, number: usize, id: String },
+    CodeBlock { lang: &'a str, code: Cow<'a, str>, attrs: CodeAttrs<'a>, caption: Option<Caption<'a>> },
     Paragraph { text: Inline<'a> },
-    Ref(Vec<(Inline<'a>, usize)>),
+    Env { kind: EnvKind, title: Option<Inline<'a>>, number: Option<String>, id: Option<&'a str>, content: Vec<Block<'a>> },
+    Ref(Vec<(Inline<'a>, usize, usize)>, Vec<(&'a str, String, BibEntry)>),
+}
+
+#[derive(Debug)]
+pub struct Cell<'a> {
+    pub text: Inline<'a>,
+    pub colspan: usize,
+    pub rowspan: usize,
+    pub align: Option<Align>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct MediaAttrs<'a> {
+    pub alt: String,
+    pub width: Option<Cow<'a, str>>,
+    pub height: Option<Cow<'a, str>>,
+    pub classes: Vec<&'a str>,
+    pub loading: &'a str,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MediaKind {
+    Image,
+    Video,
+    Audio,
+    Frame,
+    Document,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Debug)]
+pub struct Caption<'a> {
+    pub name: &'static str,
+    pub text: Inline<'a>,
+    pub number: Option<usize>,
+    pub id: Option<&'a str>,
+}
+
+#[derive(Debug, Default)]
+pub struct CodeAttrs<'a> {
+    pub title: Option<&'a str>,
+    pub linenos: Option<usize>,
+    pub hl_lines: Vec<(usize, usize)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EnvKind {
+    Theorem,
+    Lemma,
+    Definition,
+    Example,
+    Proof,
 }
 
 #[derive(Debug)]
@@ -34,17 +116,36 @@ pub struct Inline<'a>(pub Vec<Span<'a>>);
 
 #[derive(Clone, Debug)]
 pub enum Span<'a> {
-    Cite { id: usize },
-    Link { text: Inline<'a>, url: Cow<'a, str> },
+    Cite { id: usize, count: usize, note: Option<Inline<'a>> },
+    Citation { items: Vec<(&'a str, String)>, numeric: bool },
+    Link { text: Inline<'a>, url: Cow<'a, str>, title: Option<&'a str> },
+    WikiLink { target: &'a str, fragment: Option<&'a str>, label: &'a str, url: Option<String> },
     Bold { text: Inline<'a> },
     Ital { text: Inline<'a> },
-    Math { math: &'a str },
+    Strike { text: Inline<'a> },
+    Mark { text: Inline<'a> },
+    Underline { text: Inline<'a> },
+    Sup { text: Inline<'a> },
+    Sub { text: Inline<'a> },
+    Kbd { key: &'a str },
+    Math { math: Cow<'a, str>, mathml: Option<String> },
     Code { code: &'a str },
+    Image { url: Cow<'a, str>, attrs: MediaAttrs<'a> },
+    CrossRef { label: &'a str, text: String },
     Text { text: Cow<'a, str> },
 }
 
+#[derive(Debug, Default)]
+pub struct Attrs<'a> {
+    pub id: Option<&'a str>,
+    pub classes: Vec<&'a str>,
+    pub pairs: Vec<(&'a str, &'a str)>,
+}
+
 pub struct HtmlText<'a>(pub &'a str);
 pub struct MathText<'a>(pub &'a str);
+pub struct IdAttr<'a>(pub Option<&'a str>);
+pub struct AttrText<'a>(pub &'a str);
 
 #[derive(Debug)]
 pub enum Elem {
@@ -58,12 +159,206 @@ pub enum Elem {
     Second,
     Toc(usize),
     Content(usize),
+    Macros(usize),
+    Backlinks(usize),
     Str(String),
 }
 
 pub enum SyntaxError {
     Expect(&'static [&'static str]),
     Empty,
+    UndefinedLabel(String),
+    UndefinedCitation(String),
+    NotFound(String),
+    BadRange(String),
+    Cycle(String),
+    Line(usize, Box<SyntaxError>),
+    File(String, Box<SyntaxError>),
+}
+
+impl MediaKind {
+    pub fn class(&self) -> &'static str {
+        match self {
+            MediaKind::Image => "image",
+            MediaKind::Video => "video",
+            MediaKind::Audio => "audio",
+            MediaKind::Frame => "frame",
+            MediaKind::Document => "document",
+        }
+    }
+}
+
+impl Align {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Align::Left => "left",
+            Align::Center => "center",
+            Align::Right => "right",
+        }
+    }
+}
+
+impl<'a> std::fmt::Display for Cell<'a> {
+    // the attributes of the cell
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.colspan > 1 {
+            write!(f, " colspan=\"{}\"", self.colspan)?;
+        }
+        if self.rowspan > 1 {
+            write!(f, " rowspan=\"{}\"", self.rowspan)?;
+        }
+        if let Some(align) = self.align {
+            write!(f, " style=\"text-align: {}\"", align.name())?;
+        }
+        Ok(())
+    }
+}
+
+impl EnvKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "theorem" => Some(EnvKind::Theorem),
+            "lemma" => Some(EnvKind::Lemma),
+            "definition" => Some(EnvKind::Definition),
+            "example" => Some(EnvKind::Example),
+            "proof" => Some(EnvKind::Proof),
+            _ => None,
+        }
+    }
+
+    pub fn class(&self) -> &'static str {
+        match self {
+            EnvKind::Theorem => "theorem",
+            EnvKind::Lemma => "lemma",
+            EnvKind::Definition => "definition",
+            EnvKind::Example => "example",
+            EnvKind::Proof => "proof",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EnvKind::Theorem => "Theorem",
+            EnvKind::Lemma => "Lemma",
+            EnvKind::Definition => "Definition",
+            EnvKind::Example => "Example",
+            EnvKind::Proof => "Proof",
+        }
+    }
+}
+
+impl<'a> Attrs<'a> {
+    // parse attributes such as `#label .class key=value key="quoted value" flag`
+    pub fn parse(mut chs: &'a str) -> Self {
+        let mut attrs = Attrs::default();
+        loop {
+            chs = chs.trim_start();
+            if chs.is_empty() {
+                return attrs;
+            }
+
+            let len = chs.find(char::is_whitespace).unwrap_or(chs.len());
+            let (token, rest) = match chs.find('=') {
+                Some(eq) if eq < len && chs[eq + 1..].starts_with('"') => {
+                    let close = chs[eq + 2..].find('"').map_or(chs.len(), |i| eq + 2 + i);
+                    attrs.pairs.push((&chs[..eq], &chs[eq + 2..close]));
+                    chs = chs.get(close + 1..).unwrap_or("");
+                    continue;
+                }
+                _ => chs.split_at(len),
+            };
+            chs = rest;
+
+            if let Some(id) = token.strip_prefix('#') {
+                attrs.id = Some(id);
+            } else if let Some(class) = token.strip_prefix('.') {
+                attrs.classes.push(class);
+            } else if let Some((key, value)) = token.split_once('=') {
+                attrs.pairs.push((key, value));
+            } else {
+                attrs.pairs.push((token, ""));
+            }
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&'a str> {
+        self.pairs.iter().find(|(k, _)| *k == key).map(|&(_, v)| v)
+    }
+}
+
+impl<'a> Block<'a> {
+    pub fn walk_spans_mut<E>(&mut self, f: &mut impl FnMut(&mut Span<'a>) -> Result<(), E>) -> Result<(), E> {
+        match self {
+            Header { header, .. } => header.walk_spans_mut(f),
+            Blockquote { lines } => lines.iter_mut().try_for_each(|line| line.walk_spans_mut(f)),
+            ListBlock(list) => list.walk_spans_mut(f),
+            Media { caption, .. } => caption.text.walk_spans_mut(f),
+            Table { head, body, caption } => {
+                if let Some(caption) = caption {
+                    caption.text.walk_spans_mut(f)?;
+                }
+                head.iter_mut().chain(body.iter_mut()).flatten().try_for_each(|cell| cell.text.walk_spans_mut(f))
+            }
+            CodeBlock { caption: Some(caption), .. } => caption.text.walk_spans_mut(f),
+            Paragraph { text } => text.walk_spans_mut(f),
+            Env { title, content, .. } => {
+                if let Some(title) = title {
+                    title.walk_spans_mut(f)?;
+                }
+                content.iter_mut().try_for_each(|block| block.walk_spans_mut(f))
+            }
+            Ref(notes, _) => notes.iter_mut().try_for_each(|(note, _, _)| note.walk_spans_mut(f)),
+            LinkCard { .. } | MathBlock { .. } | CodeBlock { .. } => Ok(()),
+        }
+    }
+}
+
+impl<'a> List<'a> {
+    pub fn walk_spans_mut<E>(&mut self, f: &mut impl FnMut(&mut Span<'a>) -> Result<(), E>) -> Result<(), E> {
+        for ListItem { item, list } in &mut self.items {
+            item.walk_spans_mut(f)?;
+            list.walk_spans_mut(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Inline<'a> {
+    pub fn walk_spans_mut<E>(&mut self, f: &mut impl FnMut(&mut Span<'a>) -> Result<(), E>) -> Result<(), E> {
+        for span in &mut self.0 {
+            f(span)?;
+            match span {
+                Link { text, .. } | Bold { text } | Ital { text } | Strike { text } | Mark { text } | Underline { text } | Sup { text } | Sub { text } => {
+                    text.walk_spans_mut(f)?
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Inline<'a> {
+    // the text without markup, such as the alternative text of images
+    pub fn plain_text(&self) -> String {
+        let mut res = String::new();
+        for span in &self.0 {
+            match span {
+                Link { text, .. } | Bold { text } | Ital { text } | Strike { text } | Mark { text } | Underline { text } | Sup { text } | Sub { text } => {
+                    res.push_str(&text.plain_text())
+                }
+                Kbd { key } => res.push_str(key),
+                WikiLink { label, .. } => res.push_str(label),
+                Math { math, .. } => res.push_str(math),
+                Code { code } => res.push_str(code),
+                Image { attrs, .. } => res.push_str(&attrs.alt),
+                CrossRef { text, .. } => res.push_str(text),
+                Text { text } => res.push_str(text),
+                _ => {}
+            }
+        }
+        res
+    }
 }
 
 impl<'a> std::fmt::Display for Inline<'a> {
@@ -78,17 +373,60 @@ impl<'a> std::fmt::Display for Inline<'a> {
 impl<'a> std::fmt::Display for Span<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
-            Cite { id} => write!(f, "<sup id=\"cite-{id}\"><a href=\"#ref-{id}\">[{id}]</a></sup>"),
-            Link { text, url } => write!(f, "<a href=\"{url}\">{text}</a>"),
+            Cite { id, count, note } => {
+                write!(f, "<sup id=\"{}\"><a href=\"#ref-{id}\">[{id}]</a></sup>", cite_id(*id, *count))?;
+                match note {
+                    Some(note) => write!(f, "<span class=\"sidenote\"><span class=\"sidenote-number\">[{id}]</span> {note}</span>"),
+                    None => Ok(()),
+                }
+            }
+            Citation { items, numeric } => {
+                let (open, sep, close) = if *numeric { ("[", ", ", "]") } else { ("(", "; ", ")") };
+                write!(f, "<span class=\"citation\">{open}")?;
+                for (i, (key, label)) in items.iter().enumerate() {
+                    let sep = if i == 0 { "" } else { sep };
+                    write!(f, "{sep}<a href=\"#bib-{key}\">{label}</a>")?;
+                }
+                write!(f, "{close}</span>")
+            }
+            WikiLink { label, url: Some(url), .. } => write!(f, "<a class=\"wikilink\" href=\"{url}\">{}</a>", HtmlText(label)),
+            WikiLink { label, url: None, .. } => write!(f, "<span class=\"wikilink broken\">{}</span>", HtmlText(label)),
+            Link { text, url, title: Some(title) } => write!(f, "<a href=\"{url}\" title=\"{}\">{text}</a>", AttrText(title)),
+            Link { text, url, .. } => write!(f, "<a href=\"{url}\">{text}</a>"),
             Bold { text } => write!(f, "<strong>{text}</strong>"),
+            Strike { text } => write!(f, "<del>{text}</del>"),
+            Mark { text } => write!(f, "<mark>{text}</mark>"),
+            Underline { text } => write!(f, "<u>{text}</u>"),
+            Sup { text } => write!(f, "<sup>{text}</sup>"),
+            Sub { text } => write!(f, "<sub>{text}</sub>"),
+            Kbd { key } => write!(f, "<kbd>{}</kbd>", HtmlText(key)),
             Ital { text } => write!(f, "<em>{text}</em>"),
-            Math { math } => write!(f, "\\({}\\)", MathText(math)),
+            Math { mathml: Some(mathml), .. } => write!(f, "{mathml}"),
+            Math { math, .. } => write!(f, "\\({}\\)", MathText(math)),
             Code { code } => write!(f, "<code>{}</code>", HtmlText(code)),
+            Image { url, attrs } => {
+                write!(f, "<img class=\"inline")?;
+                for class in &attrs.classes {
+                    write!(f, " {class}")?;
+                }
+                write!(f, "\" src=\"{url}\" alt=\"{}\"{attrs} loading=\"{}\">", AttrText(&attrs.alt), attrs.loading)
+            }
+            CrossRef { label, text } => write!(f, "<a href=\"#{label}\">{text}</a>"),
             Text { text } => write!(f, "{}", HtmlText(text)),
         }
     }
 }
 
+impl<'a> std::fmt::Display for Caption<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.number {
+            Some(number) if self.text.0.is_empty() => write!(f, "{} {number}", self.name),
+            Some(number) => write!(f, "{} {number}: {}", self.name, self.text),
+            None => write!(f, "{}", self.text),
+        }
+    }
+}
+
 impl<'a> std::fmt::Display for HtmlText<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         let mut chs = self.0.chars();
@@ -114,15 +452,66 @@ impl<'a> std::fmt::Display for MathText<'a> {
     }
 }
 
+impl<'a> std::fmt::Display for IdAttr<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.0 {
+            Some(id) => write!(f, " id=\"{id}\""),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<'a> std::fmt::Display for AttrText<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '&' => write!(f, "&amp;")?,
+                '"' => write!(f, "&quot;")?,
+                c => escape(c, f)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> std::fmt::Display for MediaAttrs<'a> {
+    // the size of the media element
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if let Some(width) = &self.width {
+            write!(f, " width=\"{}\"", AttrText(width))?;
+        }
+        if let Some(height) = &self.height {
+            write!(f, " height=\"{}\"", AttrText(height))?;
+        }
+        Ok(())
+    }
+}
+
 impl std::fmt::Display for SyntaxError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             Expect(terms) => write!(f, "{:?} is expected", terms),
             Empty => write!(f, "cannot parse because of unvalid character"),
+            UndefinedLabel(label) => write!(f, "label \"{label}\" is not defined"),
+            UndefinedCitation(key) => write!(f, "citation \"{key}\" is not found in the bibliography"),
+            NotFound(path) => write!(f, "could not read \"{path}\""),
+            BadRange(range) => write!(f, "{range} is not found"),
+            Cycle(path) => write!(f, "\"{path}\" includes itself"),
+            Line(line, err) => write!(f, "line {line}: {err}"),
+            File(path, err) => write!(f, "{path}: {err}"),
         }
     }
 }
 
+// the anchor of the `count`-th citation of a note
+pub fn cite_id(id: usize, count: usize) -> String {
+    if count == 1 {
+        format!("cite-{id}")
+    } else {
+        format!("cite-{id}-{count}")
+    }
+}
+
 fn escape(c: char, f: &mut std::fmt::Formatter) -> std::fmt::Result {
     match c {
         '<' => write!(f, "&lt;"),
@@ -0,0 +1,142 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::header::{HOST, LOCATION};
+use reqwest::{redirect, Client, Method, Proxy, StatusCode, Url};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+pub enum Status {
+    Ok,
+    Redirect(StatusCode, String),
+    Broken(String),
+}
+
+// checks `http(s)://` URLs with at most `concurrency` requests at a time
+pub struct ExternalChecker {
+    client: Client,
+    concurrency: usize,
+    allow: Vec<String>,
+    deny: Vec<String>,
+    base: Option<Url>,
+}
+
+impl ExternalChecker {
+    // `--timeout=<seconds>`, `--concurrency=<requests>`, `--allow=<pattern>,...`, `--deny=<pattern>,...`, `--proxy=<url>` and `--base-url=<url>`
+    pub fn new(options: &[(&str, &str)]) -> Result<Self, String> {
+        let option = |key: &str| options.iter().rev().find(|(k, _)| *k == key).map(|&(_, v)| v);
+        let patterns = |key: &str| {
+            option(key).map_or(Vec::new(), |value| value.split(',').map(str::trim).filter(|p| !p.is_empty()).map(str::to_string).collect())
+        };
+        let number = |key: &str, default: u64| match option(key) {
+            Some(value) => value.parse().map_err(|_| format!("invalid value of \"--{key}\": \"{value}\"")),
+            None => Ok(default),
+        };
+
+        let mut builder = Client::builder()
+            .timeout(Duration::from_secs(number("timeout", 10)?))
+            .redirect(redirect::Policy::none())
+            .user_agent(concat!("notex/", env!("CARGO_PKG_VERSION")));
+        if let Some(proxy) = option("proxy") {
+            builder = builder.proxy(Proxy::all(proxy).map_err(|err| format!("invalid proxy \"{proxy}\": {err}"))?);
+        }
+        let client = builder.build().map_err(|err| err.to_string())?;
+        let base = match option("base-url") {
+            Some(base) => Some(Url::parse(base).map_err(|err| format!("invalid base URL \"{base}\": {err}"))?),
+            None => None,
+        };
+
+        Ok(ExternalChecker {
+            client,
+            concurrency: number("concurrency", 8)?.max(1) as usize,
+            allow: patterns("allow"),
+            deny: patterns("deny"),
+            base,
+        })
+    }
+
+    // URLs not denied, and allowed if there is an allow list
+    pub fn is_target(&self, url: &str) -> bool {
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return false;
+        }
+        (self.allow.is_empty() || self.allow.iter().any(|pattern| matches(url, pattern))) && !self.deny.iter().any(|pattern| matches(url, pattern))
+    }
+
+    #[tokio::main]
+    pub async fn check(&self, urls: Vec<String>) -> Vec<(String, Status)> {
+        let semaphore = Arc::new(Semaphore::new(self.concurrency));
+        let mut tasks = JoinSet::new();
+        for (i, url) in urls.into_iter().enumerate() {
+            let (client, semaphore, base) = (self.client.clone(), semaphore.clone(), self.base.clone());
+            tasks.spawn(async move {
+                let _permit = semaphore.acquire_owned().await;
+                let status = request(&client, &url, base.as_ref()).await;
+                (i, url, status)
+            });
+        }
+
+        let mut res = Vec::new();
+        while let Some(task) = tasks.join_next().await {
+            if let Ok(task) = task {
+                res.push(task);
+            }
+        }
+        res.sort_by_key(|&(i, _, _)| i);
+        res.into_iter().map(|(_, url, status)| (url, status)).collect()
+    }
+}
+
+// HEAD, falling back to GET for servers rejecting it
+async fn request(client: &Client, url: &str, base: Option<&Url>) -> Status {
+    let Ok(url) = Url::parse(url) else {
+        return Status::Broken("invalid URL".to_string());
+    };
+    let send = |method: Method| {
+        let Some(base) = base else {
+            return client.request(method, url.clone()).send();
+        };
+        // the path and the query on the base URL, with the original host in the `Host` header
+        let mut target = base.clone();
+        target.set_path(&format!("{}{}", base.path().trim_end_matches('/'), url.path()));
+        target.set_query(url.query());
+        let host = url.host_str().unwrap_or_default();
+        let host = url.port().map_or(host.to_string(), |port| format!("{host}:{port}"));
+        client.request(method, target).header(HOST, host).send()
+    };
+
+    let mut res = send(Method::HEAD).await;
+    if res.as_ref().is_ok_and(|res| res.status().is_client_error() || res.status().is_server_error()) {
+        res = send(Method::GET).await;
+    }
+
+    let res = match res {
+        Ok(res) => res,
+        Err(err) if err.is_timeout() => return Status::Broken("timed out".to_string()),
+        Err(err) if err.is_connect() => return Status::Broken("could not connect".to_string()),
+        Err(err) => return Status::Broken(err.to_string()),
+    };
+    let status = res.status();
+    if status.is_redirection() {
+        let location = res.headers().get(LOCATION).and_then(|location| location.to_str().ok());
+        let location = location.and_then(|location| url.join(location).ok()).map_or(String::new(), |location| location.to_string());
+        return Status::Redirect(status, location);
+    }
+    if status.is_client_error() || status.is_server_error() {
+        return Status::Broken(status.to_string());
+    }
+    Status::Ok
+}
+
+// `example.com` matches the host and its subdomains, and `https://example.com/path` matches the URLs starting with it
+fn matches(url: &str, pattern: &str) -> bool {
+    if pattern.contains("://") {
+        return url.starts_with(pattern);
+    }
+    let host = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let host = &host[..host.find(['/', '?', '#']).unwrap_or(host.len())];
+    let host = host.rsplit_once('@').map_or(host, |(_, host)| host);
+    let host = host.split_once(':').map_or(host, |(host, _)| host).to_ascii_lowercase();
+    let pattern = pattern.to_ascii_lowercase();
+    host == pattern || host.ends_with(&format!(".{pattern}"))
+}
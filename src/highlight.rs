@@ -0,0 +1,506 @@
+// tokenize source code for build-time syntax highlighting
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Tok {
+    Plain,
+    Keyword,
+    Type,
+    Literal,
+    Number,
+    String,
+    Comment,
+    Function,
+    Meta,
+    Key,
+    Tag,
+    Attr,
+}
+
+impl Tok {
+    pub fn class(&self) -> Option<&'static str> {
+        Some(match self {
+            Tok::Plain => return None,
+            Tok::Keyword => "tok-kw",
+            Tok::Type => "tok-ty",
+            Tok::Literal => "tok-lit",
+            Tok::Number => "tok-num",
+            Tok::String => "tok-str",
+            Tok::Comment => "tok-com",
+            Tok::Function => "tok-fn",
+            Tok::Meta => "tok-meta",
+            Tok::Key => "tok-key",
+            Tok::Tag => "tok-tag",
+            Tok::Attr => "tok-attr",
+        })
+    }
+}
+
+pub const THEME: &str = "\
+pre code .tok-kw { color: #ff7b72; }
+pre code .tok-ty { color: #ffa657; }
+pre code .tok-lit { color: #79c0ff; }
+pre code .tok-num { color: #79c0ff; }
+pre code .tok-str { color: #a5d6ff; }
+pre code .tok-com { color: #8b949e; font-style: italic; }
+pre code .tok-fn { color: #d2a8ff; }
+pre code .tok-meta { color: #ffa657; }
+pre code .tok-key { color: #7ee787; }
+pre code .tok-tag { color: #7ee787; }
+pre code .tok-attr { color: #79c0ff; }
+";
+
+struct Lang {
+    line_comments: &'static [&'static str],
+    block_comment: Option<(&'static str, &'static str)>,
+    strings: &'static [&'static str],
+    keywords: &'static [&'static str],
+    types: &'static [&'static str],
+    literals: &'static [&'static str],
+    meta: &'static [&'static str],
+}
+
+const RUST: Lang = Lang {
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+    strings: &["\""],
+    keywords: &[
+        "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern", "fn", "for",
+        "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return", "self", "Self",
+        "static", "struct", "super", "trait", "type", "unsafe", "use", "where", "while",
+    ],
+    types: &[
+        "bool", "char", "str", "u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32", "i64", "i128", "isize",
+        "f32", "f64", "String", "Vec", "Option", "Result", "Box",
+    ],
+    literals: &["true", "false", "None", "Some", "Ok", "Err"],
+    meta: &["#"],
+};
+
+const PYTHON: Lang = Lang {
+    line_comments: &["#"],
+    block_comment: None,
+    strings: &["\"\"\"", "'''", "\"", "'"],
+    keywords: &[
+        "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del", "elif", "else", "except",
+        "finally", "for", "from", "global", "if", "import", "in", "is", "lambda", "nonlocal", "not", "or", "pass",
+        "raise", "return", "try", "while", "with", "yield",
+    ],
+    types: &["int", "float", "str", "bool", "list", "dict", "set", "tuple", "bytes", "object"],
+    literals: &["True", "False", "None"],
+    meta: &["@"],
+};
+
+const SHELL: Lang = Lang {
+    line_comments: &["#"],
+    block_comment: None,
+    strings: &["\"", "'"],
+    keywords: &[
+        "if", "then", "else", "elif", "fi", "for", "in", "do", "done", "while", "until", "case", "esac", "function",
+        "return", "export", "local", "readonly", "set", "unset",
+    ],
+    types: &[],
+    literals: &["true", "false"],
+    meta: &["$"],
+};
+
+const JSON: Lang = Lang {
+    line_comments: &[],
+    block_comment: None,
+    strings: &["\""],
+    keywords: &[],
+    types: &[],
+    literals: &["true", "false", "null"],
+    meta: &[],
+};
+
+const TOML: Lang = Lang {
+    line_comments: &["#"],
+    block_comment: None,
+    strings: &["\"\"\"", "'''", "\"", "'"],
+    keywords: &[],
+    types: &[],
+    literals: &["true", "false", "inf", "nan"],
+    meta: &[],
+};
+
+const GO: Lang = Lang {
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+    strings: &["\"", "`", "'"],
+    keywords: &[
+        "break", "case", "chan", "const", "continue", "default", "defer", "else", "fallthrough", "for", "func", "go",
+        "goto", "if", "import", "interface", "map", "package", "range", "return", "select", "struct", "switch", "type",
+        "var",
+    ],
+    types: &[
+        "bool", "byte", "complex64", "complex128", "error", "float32", "float64", "int", "int8", "int16", "int32",
+        "int64", "rune", "string", "uint", "uint8", "uint16", "uint32", "uint64", "uintptr", "any",
+    ],
+    literals: &["true", "false", "nil", "iota"],
+    meta: &[],
+};
+
+const C: Lang = Lang {
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+    strings: &["\"", "'"],
+    keywords: &[
+        "break", "case", "const", "continue", "default", "do", "else", "enum", "extern", "for", "goto", "if", "inline",
+        "register", "restrict", "return", "sizeof", "static", "struct", "switch", "typedef", "union", "volatile", "while",
+    ],
+    types: &[
+        "void", "char", "short", "int", "long", "float", "double", "signed", "unsigned", "bool", "size_t", "int8_t",
+        "int16_t", "int32_t", "int64_t", "uint8_t", "uint16_t", "uint32_t", "uint64_t", "FILE",
+    ],
+    literals: &["true", "false", "NULL"],
+    meta: &["#"],
+};
+
+pub fn tokenize<'a>(lang: &str, code: &'a str) -> Option<Vec<(Tok, &'a str)>> {
+    let (spec, lang) = match lang {
+        "rust" | "rs" => (&RUST, "rust"),
+        "python" | "py" => (&PYTHON, "python"),
+        "shell" | "sh" | "bash" | "zsh" | "console" => (&SHELL, "shell"),
+        "json" => (&JSON, "json"),
+        "toml" => (&TOML, "toml"),
+        "go" => (&GO, "go"),
+        "c" | "h" => (&C, "c"),
+        "html" | "xml" | "svg" => return Some(tokenize_html(code)),
+        _ => return None,
+    };
+    Some(Lexer { spec, lang, src: code, pos: 0, tokens: Vec::new(), line_start: true }.tokenize())
+}
+
+struct Lexer<'a> {
+    spec: &'static Lang,
+    lang: &'static str,
+    src: &'a str,
+    pos: usize,
+    tokens: Vec<(Tok, usize, usize)>,
+    line_start: bool,
+}
+
+impl<'a> Lexer<'a> {
+    fn tokenize(mut self) -> Vec<(Tok, &'a str)> {
+        let rust = self.lang == "rust";
+        let toml = self.lang == "toml";
+        while let Some(c) = self.rest().chars().next() {
+            let chs = self.rest();
+
+            // comment
+            if self.spec.line_comments.iter().any(|&com| chs.starts_with(com)) {
+                self.push(Tok::Comment, line_len(chs));
+                continue;
+            }
+            if let Some((open, close)) = self.spec.block_comment.filter(|(open, _)| chs.starts_with(open)) {
+                let len = chs[open.len()..].find(close).map_or(chs.len(), |i| open.len() + i + close.len());
+                self.push(Tok::Comment, len);
+                continue;
+            }
+
+            // attributes, preprocessor directives, decorators and shell variables
+            if self.spec.meta.iter().any(|&meta| chs.starts_with(meta)) {
+                let len = match c {
+                    '#' if rust => chs.find(']').map_or(0, |i| i + 1).min(line_len(chs)),
+                    '#' => line_len(chs),
+                    '$' if chs[1..].starts_with('{') => chs.find('}').map_or(chs.len(), |i| i + 1),
+                    _ => 1 + word_len(&chs[1..]),
+                };
+                if len > 1 {
+                    self.push(Tok::Meta, len);
+                    continue;
+                }
+            }
+
+            // string or key
+            if let Some(&quote) = self.spec.strings.iter().find(|&quote| chs.starts_with(quote)) {
+                let len = string_len(chs, quote);
+                let rest = chs[len..].trim_start_matches([' ', '\t']);
+                let key = (self.lang == "json" && rest.starts_with(':')) || (toml && self.line_start && rest.starts_with('='));
+                self.push(if key { Tok::Key } else { Tok::String }, len);
+                continue;
+            }
+
+            // char literal or lifetime
+            if rust && c == '\'' {
+                let rest = &chs[1..];
+                let len = if rest.starts_with('\\') {
+                    rest.find('\'').unwrap_or(0)
+                } else {
+                    rest.chars().next().map_or(0, char::len_utf8)
+                };
+                if len > 0 && rest[len..].starts_with('\'') {
+                    self.push(Tok::String, len + 2);
+                } else {
+                    self.push(Tok::Plain, 1 + word_len(rest));
+                }
+                continue;
+            }
+
+            // number, including dates and times in toml
+            if c.is_ascii_digit() {
+                let len = chs
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.' || (toml && (c == '-' || c == ':'))))
+                    .unwrap_or(chs.len());
+                self.push(Tok::Number, len);
+                continue;
+            }
+
+            // identifier
+            if c.is_alphabetic() || c == '_' {
+                let len = word_len(chs);
+                let word = &chs[..len];
+                let rest = &chs[len..];
+                let tok = if toml && self.line_start {
+                    Tok::Key
+                } else if self.spec.keywords.contains(&word) {
+                    Tok::Keyword
+                } else if self.spec.types.contains(&word) {
+                    Tok::Type
+                } else if self.spec.literals.contains(&word) {
+                    Tok::Literal
+                } else if rest.starts_with('(') || (rust && rest.starts_with('!')) {
+                    Tok::Function
+                } else if (rust || self.lang == "go") && word.starts_with(char::is_uppercase) {
+                    Tok::Type
+                } else {
+                    Tok::Plain
+                };
+                self.push(tok, len);
+                continue;
+            }
+
+            // table header
+            if toml && self.line_start && c == '[' {
+                self.push(Tok::Type, line_len(chs));
+                continue;
+            }
+
+            self.push(Tok::Plain, c.len_utf8());
+        }
+
+        let src = self.src;
+        self.tokens.into_iter().map(|(tok, start, end)| (tok, &src[start..end])).collect()
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.src[self.pos..]
+    }
+
+    fn push(&mut self, tok: Tok, len: usize) {
+        let (start, end) = (self.pos, self.pos + len);
+        let text = &self.src[start..end];
+        self.pos = end;
+
+        if text.ends_with('\n') {
+            self.line_start = true;
+        } else if !text.trim().is_empty() {
+            self.line_start = false;
+        }
+
+        match self.tokens.last_mut() {
+            Some((last, _, last_end)) if *last == tok && *last_end == start => *last_end = end,
+            _ => self.tokens.push((tok, start, end)),
+        }
+    }
+}
+
+fn line_len(chs: &str) -> usize {
+    chs.find('\n').unwrap_or(chs.len())
+}
+
+fn word_len(chs: &str) -> usize {
+    chs.find(|c: char| !c.is_alphanumeric() && c != '_').unwrap_or(chs.len())
+}
+
+fn string_len(chs: &str, quote: &str) -> usize {
+    let mut rest = chs[quote.len()..].char_indices();
+    while let Some((i, c)) = rest.next() {
+        if c == '\\' && quote != "'''" && quote != "`" {
+            rest.next();
+        } else if chs[quote.len() + i..].starts_with(quote) {
+            return quote.len() + i + quote.len();
+        } else if c == '\n' && quote.len() == 1 && quote != "`" {
+            return quote.len() + i;
+        }
+    }
+    chs.len()
+}
+
+fn tokenize_html(mut chs: &str) -> Vec<(Tok, &str)> {
+    let mut tokens = Vec::new();
+
+    while !chs.is_empty() {
+        if chs.starts_with("<!--") {
+            let len = chs.find("-->").map_or(chs.len(), |i| i + 3);
+            tokens.push(take(&mut chs, Tok::Comment, len));
+        } else if chs.starts_with("<!") || chs.starts_with("<?") {
+            let len = chs.find('>').map_or(chs.len(), |i| i + 1);
+            tokens.push(take(&mut chs, Tok::Meta, len));
+        } else if chs.starts_with('<') {
+            let len = 1 + chs[1..].find(|c: char| c.is_whitespace() || c == '>').unwrap_or(chs.len() - 1);
+            tokens.push(take(&mut chs, Tok::Tag, len));
+            // attributes
+            while !chs.is_empty() && !chs.starts_with('>') && !chs.starts_with("/>") {
+                let c = chs.chars().next().unwrap();
+                if c == '"' || c == '\'' {
+                    let len = chs[1..].find(c).map_or(chs.len(), |i| i + 2);
+                    tokens.push(take(&mut chs, Tok::String, len));
+                } else if c.is_alphabetic() {
+                    let len = chs.find(|c: char| c.is_whitespace() || c == '=' || c == '>' || c == '/').unwrap_or(chs.len());
+                    tokens.push(take(&mut chs, Tok::Attr, len));
+                } else {
+                    tokens.push(take(&mut chs, Tok::Plain, c.len_utf8()));
+                }
+            }
+            let len = if chs.starts_with("/>") { 2 } else { chs.len().min(1) };
+            tokens.push(take(&mut chs, Tok::Tag, len));
+        } else if chs.starts_with('&') {
+            let len = chs.find(';').filter(|&i| i < 10).map_or(1, |i| i + 1);
+            tokens.push(take(&mut chs, Tok::Literal, len));
+        } else {
+            let len = chs.find(['<', '&']).unwrap_or(chs.len());
+            tokens.push(take(&mut chs, Tok::Plain, len));
+        }
+    }
+    tokens
+}
+
+// split tokens into lines, dropping the newline characters
+pub fn split_lines(tokens: Vec<(Tok, &str)>) -> Vec<Vec<(Tok, &str)>> {
+    let mut lines = vec![Vec::new()];
+    for (tok, text) in tokens {
+        let mut pieces = text.split('\n');
+        if let Some(piece) = pieces.next().filter(|piece| !piece.is_empty()) {
+            lines.last_mut().unwrap().push((tok, piece));
+        }
+        for piece in pieces {
+            lines.push(Vec::new());
+            if !piece.is_empty() {
+                lines.last_mut().unwrap().push((tok, piece));
+            }
+        }
+    }
+    if lines.last().is_some_and(Vec::is_empty) {
+        lines.pop();
+    }
+    lines
+}
+
+fn take<'a>(chs: &mut &'a str, tok: Tok, len: usize) -> (Tok, &'a str) {
+    let (text, rest) = chs.split_at(len);
+    *chs = rest;
+    (tok, text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Tok::*;
+
+    // the tokens other than plain text
+    fn marked<'a>(lang: &str, code: &'a str) -> Vec<(Tok, &'a str)> {
+        tokenize(lang, code).unwrap().into_iter().filter(|&(tok, _)| tok != Plain).collect()
+    }
+
+    #[test]
+    fn rust() {
+        let code = "#[derive(Debug)]\nfn main() -> Option<u8> { let s = \"a\\\"b\"; 'c'; 'a: loop {} println!(\"{}\", 1_000); None } // end";
+        assert_eq!(
+            marked("rust", code),
+            [
+                (Meta, "#[derive(Debug)]"), (Keyword, "fn"), (Function, "main"), (Type, "Option"), (Type, "u8"),
+                (Keyword, "let"), (String, "\"a\\\"b\""), (String, "'c'"), (Keyword, "loop"), (Function, "println"),
+                (String, "\"{}\""), (Number, "1_000"), (Literal, "None"), (Comment, "// end"),
+            ]
+        );
+    }
+
+    #[test]
+    fn python() {
+        let code = "@dataclass\ndef f(x: int) -> str:\n    \"\"\"doc\n    more\"\"\"\n    return 'x' # c\n";
+        assert_eq!(
+            marked("py", code),
+            [
+                (Meta, "@dataclass"), (Keyword, "def"), (Function, "f"), (Type, "int"), (Type, "str"),
+                (String, "\"\"\"doc\n    more\"\"\""), (Keyword, "return"), (String, "'x'"), (Comment, "# c"),
+            ]
+        );
+    }
+
+    #[test]
+    fn shell() {
+        let code = "export A=1 # note\necho \"$HOME\" ${B} $C\n";
+        assert_eq!(
+            marked("sh", code),
+            [(Keyword, "export"), (Number, "1"), (Comment, "# note"), (String, "\"$HOME\""), (Meta, "${B}"), (Meta, "$C")]
+        );
+    }
+
+    #[test]
+    fn json() {
+        assert_eq!(
+            marked("json", "{\"a\": [1.5, true, \"s\"]}"),
+            [(Key, "\"a\""), (Number, "1.5"), (Literal, "true"), (String, "\"s\"")]
+        );
+    }
+
+    #[test]
+    fn toml() {
+        let code = "[server]\nhost = \"x\" # c\ndate = 2024-01-02T10:00:00\n\"quoted key\" = true\n";
+        assert_eq!(
+            marked("toml", code),
+            [
+                (Type, "[server]"), (Key, "host"), (String, "\"x\""), (Comment, "# c"), (Key, "date"),
+                (Number, "2024-01-02T10:00:00"), (Key, "\"quoted key\""), (Literal, "true"),
+            ]
+        );
+    }
+
+    #[test]
+    fn go() {
+        let code = "func F(s string) error { return nil } // x\nvar r = `a\nb`";
+        assert_eq!(
+            marked("go", code),
+            [
+                (Keyword, "func"), (Function, "F"), (Type, "string"), (Type, "error"), (Keyword, "return"),
+                (Literal, "nil"), (Comment, "// x"), (Keyword, "var"), (String, "`a\nb`"),
+            ]
+        );
+    }
+
+    #[test]
+    fn c() {
+        let code = "#include <stdio.h>\nint main(void) { /* c */ return 0; }";
+        assert_eq!(
+            marked("c", code),
+            [
+                (Meta, "#include <stdio.h>"), (Type, "int"), (Function, "main"), (Type, "void"), (Comment, "/* c */"),
+                (Keyword, "return"), (Number, "0"),
+            ]
+        );
+    }
+
+    #[test]
+    fn html() {
+        let code = "<!DOCTYPE html><a href=\"x\" id='y'>A &amp; B</a><!-- c -->";
+        assert_eq!(
+            marked("html", code),
+            [
+                (Meta, "<!DOCTYPE html>"), (Tag, "<a"), (Attr, "href"), (String, "\"x\""), (Attr, "id"), (String, "'y'"),
+                (Tag, ">"), (Literal, "&amp;"), (Tag, "</a"), (Tag, ">"), (Comment, "<!-- c -->"),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_language() {
+        assert!(tokenize("cobol", "DISPLAY 'X'.").is_none());
+    }
+
+    #[test]
+    fn lines_of_tokens() {
+        let lines = split_lines(tokenize("py", "x = '''a\nb'''\n").unwrap());
+        assert_eq!(lines, [vec![(Plain, "x = "), (String, "'''a")], vec![(String, "b'''")]]);
+    }
+}
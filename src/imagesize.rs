@@ -0,0 +1,158 @@
+// the intrinsic width and height of a PNG, JPEG, GIF, WebP or SVG image from its header
+pub fn image_size(data: &[u8]) -> Option<(u32, u32)> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Some((be32(data, 16)?, be32(data, 20)?));
+    }
+    if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        return Some((le16(data, 6)?, le16(data, 8)?));
+    }
+    if data.starts_with(b"\xff\xd8") {
+        return jpeg_size(data);
+    }
+    if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP") {
+        return webp_size(data);
+    }
+    svg_size(std::str::from_utf8(data).ok()?)
+}
+
+fn jpeg_size(data: &[u8]) -> Option<(u32, u32)> {
+    let mut pos = 2;
+    loop {
+        while *data.get(pos)? != 0xff {
+            pos += 1;
+        }
+        while *data.get(pos)? == 0xff {
+            pos += 1;
+        }
+        let marker = *data.get(pos)?;
+        pos += 1;
+        if (0xd0..=0xd9).contains(&marker) || marker == 0x01 {
+            continue;
+        }
+        let len = be16(data, pos)? as usize;
+        // start of frame markers other than DHT, JPG and DAC
+        if (0xc0..=0xcf).contains(&marker) && !matches!(marker, 0xc4 | 0xc8 | 0xcc) {
+            return Some((be16(data, pos + 5)?, be16(data, pos + 3)?));
+        }
+        pos += len;
+    }
+}
+
+fn webp_size(data: &[u8]) -> Option<(u32, u32)> {
+    match data.get(12..16)? {
+        b"VP8 " => Some((le16(data, 26)? & 0x3fff, le16(data, 28)? & 0x3fff)),
+        b"VP8L" => {
+            let bits = u32::from_le_bytes(data.get(21..25)?.try_into().ok()?);
+            Some(((bits & 0x3fff) + 1, ((bits >> 14) & 0x3fff) + 1))
+        }
+        b"VP8X" => Some((le24(data, 24)? + 1, le24(data, 27)? + 1)),
+        _ => None,
+    }
+}
+
+// the `width` and `height` of the root element in pixels, or its `viewBox`
+fn svg_size(src: &str) -> Option<(u32, u32)> {
+    let start = src.find("<svg")?;
+    let tag = &src[start..start + src[start..].find('>')?];
+    let attr = |name: &str| {
+        let pos = tag.find(&format!(" {name}="))? + name.len() + 2;
+        let quote = tag[pos..].chars().next()?;
+        let value = &tag[pos + 1..];
+        Some(&value[..value.find(quote)?])
+    };
+    let pixels = |value: &str| value.trim().trim_end_matches("px").parse::<f64>().ok().map(|value| value.round() as u32);
+
+    if let (Some(width), Some(height)) = (attr("width").and_then(pixels), attr("height").and_then(pixels)) {
+        return Some((width, height));
+    }
+    let view_box: Vec<f64> = attr("viewBox")?
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|value| !value.is_empty())
+        .filter_map(|value| value.parse().ok())
+        .collect();
+    match view_box.as_slice() {
+        [_, _, width, height] => Some((width.round() as u32, height.round() as u32)),
+        _ => None,
+    }
+}
+
+fn be16(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u16::from_be_bytes(data.get(pos..pos + 2)?.try_into().ok()?) as u32)
+}
+
+fn be32(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+fn le16(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u16::from_le_bytes(data.get(pos..pos + 2)?.try_into().ok()?) as u32)
+}
+
+fn le24(data: &[u8], pos: usize) -> Option<u32> {
+    let bytes = data.get(pos..pos + 3)?;
+    Some(bytes[0] as u32 | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a RIFF header of a WebP image with the chunk `kind` followed by `data`
+    fn webp(kind: &[u8], data: &[u8]) -> Vec<u8> {
+        [b"RIFF\0\0\0\0WEBP", kind, b"\0\0\0\0", data].concat()
+    }
+
+    #[test]
+    fn png() {
+        let data = [b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".as_slice(), &640u32.to_be_bytes(), &480u32.to_be_bytes()].concat();
+        assert_eq!(image_size(&data), Some((640, 480)));
+        assert_eq!(image_size(&data[..20]), None);
+    }
+
+    #[test]
+    fn gif() {
+        assert_eq!(image_size(b"GIF89a\x20\x03\x58\x02"), Some((800, 600)));
+        assert_eq!(image_size(b"GIF87a\x01\x00\x02\x00"), Some((1, 2)));
+    }
+
+    #[test]
+    fn jpeg() {
+        let app0 = b"\xff\xe0\x00\x10JFIF\0\x01\x01\0\0\x01\0\x01\0\0";
+        let dht = b"\xff\xc4\x00\x04\0\0";
+        let sof2 = b"\xff\xc2\x00\x11\x08\x01\xe0\x02\x80\x03";
+        let data = [b"\xff\xd8".as_slice(), app0, dht, sof2].concat();
+        assert_eq!(image_size(&data), Some((640, 480)));
+        assert_eq!(image_size(&[b"\xff\xd8".as_slice(), app0].concat()), None);
+    }
+
+    #[test]
+    fn webp_lossy() {
+        let data = webp(b"VP8 ", b"\0\0\0\x9d\x01\x2a\x80\x02\xe0\x01");
+        assert_eq!(image_size(&data), Some((640, 480)));
+    }
+
+    #[test]
+    fn webp_lossless() {
+        // 14 bits of the width minus one and then of the height minus one
+        let bits = 639u32 | (479 << 14);
+        let data = webp(b"VP8L", &[b"\x2f".as_slice(), &bits.to_le_bytes()].concat());
+        assert_eq!(image_size(&data), Some((640, 480)));
+    }
+
+    #[test]
+    fn webp_extended() {
+        let data = webp(b"VP8X", b"\x10\0\0\0\x7f\x02\0\xdf\x01\0");
+        assert_eq!(image_size(&data), Some((640, 480)));
+        assert_eq!(image_size(&webp(b"ALPH", b"\0\0\0\0\0\0")), None);
+    }
+
+    #[test]
+    fn svg() {
+        let svg = |tag: &str| image_size(format!("<?xml version=\"1.0\"?>\n{tag}<rect/></svg>").as_bytes());
+        assert_eq!(svg("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"120px\" height='80.4'>"), Some((120, 80)));
+        assert_eq!(svg("<svg viewBox=\"0 0 24.5,12\">"), Some((25, 12)));
+        assert_eq!(svg("<svg width=\"100%\" height=\"100%\" viewBox=\"0 0 10 20\">"), Some((10, 20)));
+        assert_eq!(svg("<svg>"), None);
+        assert_eq!(image_size(b"plain text"), None);
+    }
+}
@@ -0,0 +1,78 @@
+use crate::data::Macro;
+
+// definitions are written one per line as `\name: body` or `\name[args]: body`
+pub fn parse_macro(key: &str, body: &str) -> Option<Macro> {
+    let key = key.strip_prefix('\\')?;
+    let (name, args) = match key.split_once('[') {
+        Some((name, args)) => (name, args.strip_suffix(']')?.parse().ok()?),
+        None => (key, 0),
+    };
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    Some(Macro { name: name.to_string(), args, body: body.to_string() })
+}
+
+pub fn read_macros(src: &str) -> Vec<Macro> {
+    src.lines()
+        .filter_map(|line| line.split_once(':'))
+        .filter_map(|(key, body)| parse_macro(key.trim(), body.trim()))
+        .collect()
+}
+
+pub fn expand(math: &str, macros: &Vec<Macro>) -> String {
+    expand_depth(math, macros, 0)
+}
+
+fn expand_depth(math: &str, macros: &Vec<Macro>, depth: usize) -> String {
+    let mut res = String::new();
+    let mut chs = math;
+    while let Some(pos) = chs.find('\\') {
+        res.push_str(&chs[..pos]);
+        chs = &chs[pos + 1..];
+
+        let len = chs.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(chs.len());
+        let name = &chs[..len];
+        let Some(mac) = macros.iter().find(|mac| mac.name == name).filter(|_| len > 0 && depth < 32) else {
+            // not a macro or an escaped character such as `\{`
+            let len = if len == 0 { chs.chars().next().map_or(0, char::len_utf8) } else { len };
+            res.push('\\');
+            res.push_str(&chs[..len]);
+            chs = &chs[len..];
+            continue;
+        };
+        chs = &chs[len..];
+
+        let mut body = mac.body.clone();
+        for i in 1..=mac.args {
+            let Some((arg, rest)) = read_arg(chs) else {
+                break;
+            };
+            body = body.replace(&format!("#{i}"), arg);
+            chs = rest;
+        }
+        res.push_str(&expand_depth(&body, macros, depth + 1));
+    }
+    res.push_str(chs);
+    res
+}
+
+// a brace group `{...}` or a single token
+fn read_arg(chs: &str) -> Option<(&str, &str)> {
+    let chs = chs.trim_start();
+    if let Some(rest) = chs.strip_prefix('{') {
+        let mut depth = 0;
+        for (i, c) in rest.char_indices() {
+            match c {
+                '{' => depth += 1,
+                '}' if depth == 0 => return Some((&rest[..i], &rest[i + 1..])),
+                '}' => depth -= 1,
+                _ => {}
+            }
+        }
+        None
+    } else {
+        let len = chs.chars().next()?.len_utf8();
+        Some(chs.split_at(len))
+    }
+}
@@ -1,35 +1,79 @@
+pub mod bib;
+pub mod check;
+pub mod csv;
 pub mod data;
+pub mod external;
+pub mod highlight;
+pub mod imagesize;
+pub mod macros;
+pub mod mathml;
+pub mod media;
 pub mod multiset;
 pub mod parser;
+pub mod site;
 pub mod template;
 pub mod codegen;
 
 use std::env;
 use std::fs::{self, File};
 use std::path::Path;
+use std::process;
 
-use crate::parser::parse;
+use crate::parser::{parse, Includes};
 use crate::template::read_template;
 use crate::codegen::gen_html;
+use crate::highlight::THEME;
+use crate::check::check;
+use crate::site::{build_site, resolve_wiki_links, warn_wiki_links, Page};
 
 fn main(){
-    let args: Vec<String> = env::args().collect();
-    let temp_path = &args[1];
-    let src_path = &args[2];
+    // options `--key=value` (or `--key` for `true`) are defaults for the front matter
+    let (flags, args): (Vec<String>, Vec<String>) = env::args().skip(1).partition(|arg| arg.starts_with("--"));
+    let options: Vec<(&str, &str)> = flags
+        .iter()
+        .map(|flag| flag[2..].split_once('=').unwrap_or((&flag[2..], "true")))
+        .collect();
+
+    if args.first().map(String::as_str) == Some("theme") {
+        print!("{THEME}");
+        return;
+    }
+
+    if args.first().map(String::as_str) == Some("check") {
+        if !check(&args[1..], &options) {
+            process::exit(1);
+        }
+        return;
+    }
+
+    if args.first().map(String::as_str) == Some("site") {
+        build_site(&args[1], Path::new(&args[2]), Path::new(&args[3]), &options);
+        return;
+    }
+
+    let temp_path = &args[0];
+    let src_path = &args[1];
     let dest_path = &format!("{}.html", src_path.trim_end_matches(".md"));
-    let dest_path = if args.len() <= 3 { &dest_path } else { &args[3] };
+    let dest_path = if args.len() <= 2 { dest_path } else { &args[2] };
 
     let Ok(doc) = fs::read_to_string(src_path) else {
         println!("could not open the source file.");
         return;
     };
 
-    let (title, toc, content) = match parse(&doc) {
+    let includes = Includes::read(Path::new(src_path), &doc);
+    let doc = match parse(&doc, Path::new(src_path), &includes, &options) {
         Ok(res) => res,
         Err(err) => { println!("Syntax Error: {err}"); return; },
     };
     let file = Path::new(dest_path).file_stem().unwrap().to_str().unwrap();
 
+    // wiki links of a single document can only refer to itself
+    let mut pages = [Page { src: src_path.into(), out: Path::new(dest_path).file_name().unwrap().into(), doc }];
+    let broken = resolve_wiki_links(&mut pages);
+    warn_wiki_links(&pages, &broken);
+    let [Page { doc, .. }] = pages;
+
     let Ok(temp) = read_template(temp_path) else {
         println!("could not open or read the template file.");
         return;
@@ -40,7 +84,7 @@ fn main(){
         return;
     };
     
-    let Ok(_) = gen_html(&mut dest, file, &title, &toc, &content, &temp) else {
+    let Ok(_) = gen_html(&mut dest, file, &doc, &temp) else {
         println!("could not write to the destination file.");
         return;
     };
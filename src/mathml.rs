@@ -0,0 +1,542 @@
+// convert a subset of LaTeX into MathML
+
+pub fn to_mathml(tex: &str, display: bool) -> Result<String, String> {
+    let mut conv = Converter { chs: tex, display };
+    let row = conv.parse_row()?;
+    if let Some(token) = conv.next_token() {
+        return Err(format!("unexpected \"{}\"", token.text()));
+    }
+
+    let display = if display { " display=\"block\"" } else { "" };
+    Ok(format!("<math{display}>{}</math>", mrow(row)))
+}
+
+struct Converter<'a> {
+    chs: &'a str,
+    display: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Token<'a> {
+    Command(&'a str),
+    Char(char),
+}
+
+struct Node {
+    xml: String,
+    limits: bool,
+}
+
+impl<'a> Token<'a> {
+    fn text(&self) -> String {
+        match self {
+            Token::Command(name) => format!("\\{name}"),
+            Token::Char(c) => c.to_string(),
+        }
+    }
+}
+
+impl<'a> Converter<'a> {
+    fn parse_row(&mut self) -> Result<Vec<Node>, String> {
+        let mut row = Vec::new();
+        while let Some(node) = self.parse_term()? {
+            row.push(node);
+        }
+        Ok(row)
+    }
+
+    fn parse_term(&mut self) -> Result<Option<Node>, String> {
+        let Some(base) = self.parse_atom()? else {
+            return Ok(None);
+        };
+
+        let mut sub = None;
+        let mut sup = None;
+        loop {
+            match self.peek_token() {
+                Some(Token::Char('_')) => {
+                    self.next_token();
+                    sub = Some(self.parse_arg()?);
+                }
+                Some(Token::Char('^')) => {
+                    self.next_token();
+                    sup = Some(self.parse_arg()?);
+                }
+                Some(Token::Char('\'')) => {
+                    self.next_token();
+                    sup = Some(match sup {
+                        Some(sup) => format!("<mrow><mo>&#x2032;</mo>{sup}</mrow>"),
+                        None => "<mo>&#x2032;</mo>".to_string(),
+                    });
+                }
+                _ => break,
+            }
+        }
+
+        let (under, over, both) = if base.limits && self.display {
+            ("munder", "mover", "munderover")
+        } else {
+            ("msub", "msup", "msubsup")
+        };
+        let xml = match (sub, sup) {
+            (None, None) => return Ok(Some(base)),
+            (Some(sub), None) => format!("<{under}>{}{sub}</{under}>", base.xml),
+            (None, Some(sup)) => format!("<{over}>{}{sup}</{over}>", base.xml),
+            (Some(sub), Some(sup)) => format!("<{both}>{}{sub}{sup}</{both}>", base.xml),
+        };
+        Ok(Some(Node { xml, limits: false }))
+    }
+
+    // a required argument such as the operand of a script or a command
+    fn parse_arg(&mut self) -> Result<String, String> {
+        self.skip_space();
+        if let Some(c) = self.chs.chars().next().filter(char::is_ascii_digit) {
+            self.chs = &self.chs[1..];
+            return Ok(format!("<mn>{c}</mn>"));
+        }
+        match self.parse_atom()? {
+            Some(node) => Ok(node.xml),
+            None => Err("missing argument".to_string()),
+        }
+    }
+
+    fn parse_group(&mut self) -> Result<String, String> {
+        if self.next_token() != Some(Token::Char('{')) {
+            return Err("\"{\" is expected".to_string());
+        }
+        let row = self.parse_row()?;
+        self.expect(Token::Char('}'))?;
+        Ok(mrow(row))
+    }
+
+    // the raw text of a group `{...}`
+    fn read_group(&mut self) -> Result<&'a str, String> {
+        self.skip_space();
+        let Some(rest) = self.chs.strip_prefix('{') else {
+            return Err("\"{\" is expected".to_string());
+        };
+        let mut depth = 0;
+        for (i, c) in rest.char_indices() {
+            match c {
+                '{' => depth += 1,
+                '}' if depth == 0 => {
+                    self.chs = &rest[i + 1..];
+                    return Ok(&rest[..i]);
+                }
+                '}' => depth -= 1,
+                _ => {}
+            }
+        }
+        Err("\"}\" is expected".to_string())
+    }
+
+    fn parse_atom(&mut self) -> Result<Option<Node>, String> {
+        let Some(token) = self.peek_token() else {
+            return Ok(None);
+        };
+        let xml = match token {
+            Token::Char('}' | '&' | '_' | '^') | Token::Command("\\" | "end" | "right") => return Ok(None),
+            Token::Char('{') => self.parse_group()?,
+            Token::Char(c) if c.is_ascii_digit() || c == '.' => {
+                self.skip_space();
+                let len = self.chs.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(self.chs.len());
+                let (num, rest) = self.chs.split_at(len);
+                self.chs = rest;
+                format!("<mn>{num}</mn>")
+            }
+            Token::Char(c) => {
+                self.next_token();
+                match c {
+                    '-' => "<mo>&#x2212;</mo>".to_string(),
+                    '~' => "<mspace width=\"0.25em\"></mspace>".to_string(),
+                    '+' | '=' | '<' | '>' | '(' | ')' | '[' | ']' | '|' | ',' | ';' | ':' | '!' | '/' | '*' | '?' => {
+                        format!("<mo>{}</mo>", Escape(&c.to_string()))
+                    }
+                    c => format!("<mi>{}</mi>", Escape(&c.to_string())),
+                }
+            }
+            Token::Command(name) => {
+                self.next_token();
+                return self.parse_command(name).map(Some);
+            }
+        };
+        Ok(Some(Node { xml, limits: false }))
+    }
+
+    fn parse_command(&mut self, name: &'a str) -> Result<Node, String> {
+        if let Some(c) = greek(name) {
+            let variant = if name.starts_with(char::is_uppercase) { " mathvariant=\"normal\"" } else { "" };
+            return Ok(node(format!("<mi{variant}>{c}</mi>")));
+        }
+        if let Some(c) = symbol(name) {
+            return Ok(node(format!("<mo>{c}</mo>")));
+        }
+        if let Some(c) = ordinary(name) {
+            return Ok(node(format!("<mi>{c}</mi>")));
+        }
+        if let Some(c) = big_operator(name) {
+            return Ok(Node { xml: format!("<mo>{c}</mo>"), limits: true });
+        }
+        if let Some(c) = integral(name) {
+            return Ok(node(format!("<mo>{c}</mo>")));
+        }
+        if FUNCTIONS.contains(&name) {
+            return Ok(node(format!("<mi>{name}</mi>")));
+        }
+        if LIMIT_FUNCTIONS.contains(&name) {
+            return Ok(Node { xml: format!("<mi>{name}</mi>"), limits: true });
+        }
+        if let Some(width) = space(name) {
+            return Ok(node(format!("<mspace width=\"{width}\"></mspace>")));
+        }
+        if let Some(accent) = accent(name) {
+            let base = self.parse_arg()?;
+            return Ok(node(format!("<mover accent=\"true\">{base}<mo>{accent}</mo></mover>")));
+        }
+
+        let xml = match name {
+            "frac" | "dfrac" | "tfrac" => {
+                let num = self.parse_arg()?;
+                let den = self.parse_arg()?;
+                format!("<mfrac>{num}{den}</mfrac>")
+            }
+            "binom" => {
+                let n = self.parse_arg()?;
+                let k = self.parse_arg()?;
+                format!("<mrow><mo>(</mo><mfrac linethickness=\"0\">{n}{k}</mfrac><mo>)</mo></mrow>")
+            }
+            "sqrt" => {
+                self.skip_space();
+                if self.chs.starts_with('[') {
+                    self.chs = &self.chs[1..];
+                    let index = mrow(self.parse_row_until(']')?);
+                    let base = self.parse_arg()?;
+                    format!("<mroot>{base}{index}</mroot>")
+                } else {
+                    format!("<msqrt>{}</msqrt>", self.parse_arg()?)
+                }
+            }
+            "underline" => format!("<munder accentunder=\"true\">{}<mo>_</mo></munder>", self.parse_arg()?),
+            "text" | "textrm" | "mbox" => format!("<mtext>{}</mtext>", Escape(self.read_group()?)),
+            "operatorname" => format!("<mi>{}</mi>", Escape(self.read_group()?)),
+            "mathrm" | "mathbf" | "mathit" | "mathbb" | "mathcal" | "mathfrak" | "mathsf" | "mathtt" => {
+                let variant = match name {
+                    "mathrm" => "normal",
+                    "mathbf" => "bold",
+                    "mathit" => "italic",
+                    "mathbb" => "double-struck",
+                    "mathcal" => "script",
+                    "mathfrak" => "fraktur",
+                    "mathsf" => "sans-serif",
+                    _ => "monospace",
+                };
+                let text = self.read_group()?.trim();
+                if text.is_empty() || !text.chars().all(|c| c.is_alphanumeric() || c == ' ') {
+                    return Err(format!("unsupported argument of \\{name}"));
+                }
+                format!("<mi mathvariant=\"{variant}\">{text}</mi>")
+            }
+            "left" => {
+                let open = self.parse_delimiter()?;
+                let row = self.parse_row()?;
+                self.expect(Token::Command("right"))?;
+                let close = self.parse_delimiter()?;
+                format!("<mrow>{open}{}{close}</mrow>", row.into_iter().map(|node| node.xml).collect::<String>())
+            }
+            "big" | "Big" | "bigg" | "Bigg" | "bigl" | "bigr" | "Bigl" | "Bigr" | "biggl" | "biggr" | "Biggl" | "Biggr" => {
+                self.parse_delimiter()?
+            }
+            "begin" => self.parse_env()?,
+            "displaystyle" | "textstyle" | "limits" | "nolimits" => String::new(),
+            _ => return Err(format!("unsupported command \\{name}")),
+        };
+        Ok(node(xml))
+    }
+
+    fn parse_row_until(&mut self, close: char) -> Result<Vec<Node>, String> {
+        let mut row = Vec::new();
+        loop {
+            self.skip_space();
+            if let Some(rest) = self.chs.strip_prefix(close) {
+                self.chs = rest;
+                return Ok(row);
+            }
+            match self.parse_term()? {
+                Some(node) => row.push(node),
+                None => return Err(format!("\"{close}\" is expected")),
+            }
+        }
+    }
+
+    fn parse_delimiter(&mut self) -> Result<String, String> {
+        let delim = match self.next_token() {
+            Some(Token::Char('.')) => return Ok(String::new()),
+            Some(Token::Char(c @ ('(' | ')' | '[' | ']' | '|' | '/'))) => c.to_string(),
+            Some(Token::Command("{" | "lbrace")) => "{".to_string(),
+            Some(Token::Command("}" | "rbrace")) => "}".to_string(),
+            Some(Token::Command("|")) => "&#x2016;".to_string(),
+            Some(Token::Command(name)) => match symbol(name) {
+                Some(c) => c.to_string(),
+                None => return Err(format!("unsupported delimiter \\{name}")),
+            },
+            _ => return Err("delimiter is expected".to_string()),
+        };
+        Ok(format!("<mo fence=\"true\">{delim}</mo>"))
+    }
+
+    fn parse_env(&mut self) -> Result<String, String> {
+        let name = self.read_group()?;
+        let (open, close, align) = match name {
+            "matrix" | "smallmatrix" => ("", "", None),
+            "pmatrix" => ("(", ")", None),
+            "bmatrix" => ("[", "]", None),
+            "Bmatrix" => ("{", "}", None),
+            "vmatrix" => ("|", "|", None),
+            "Vmatrix" => ("&#x2016;", "&#x2016;", None),
+            "cases" => ("{", "", Some("left left")),
+            "aligned" | "align" | "align*" | "split" => ("", "", Some("right left")),
+            "gathered" | "gather" | "gather*" => ("", "", None),
+            "array" => {
+                self.read_group()?;
+                ("", "", None)
+            }
+            _ => return Err(format!("unsupported environment {name}")),
+        };
+
+        let mut rows = String::new();
+        loop {
+            let mut cells = String::new();
+            loop {
+                cells.push_str(&format!("<mtd>{}</mtd>", mrow(self.parse_row()?)));
+                if self.peek_token() != Some(Token::Char('&')) {
+                    break;
+                }
+                self.next_token();
+            }
+            rows.push_str(&format!("<mtr>{cells}</mtr>"));
+            if self.peek_token() != Some(Token::Command("\\")) {
+                break;
+            }
+            self.next_token();
+        }
+        self.expect(Token::Command("end"))?;
+        if self.read_group()? != name {
+            return Err(format!("\\end{{{name}}} is expected"));
+        }
+
+        let align = align.map_or(String::new(), |align| format!(" columnalign=\"{align}\""));
+        let table = format!("<mtable{align}>{rows}</mtable>");
+        if open.is_empty() && close.is_empty() {
+            return Ok(table);
+        }
+        let close = if close.is_empty() { String::new() } else { format!("<mo fence=\"true\">{close}</mo>") };
+        Ok(format!("<mrow><mo fence=\"true\">{open}</mo>{table}{close}</mrow>"))
+    }
+
+    fn expect(&mut self, token: Token) -> Result<(), String> {
+        if self.next_token() == Some(token) {
+            Ok(())
+        } else {
+            Err(format!("\"{}\" is expected", token.text()))
+        }
+    }
+
+    fn skip_space(&mut self) {
+        self.chs = self.chs.trim_start();
+    }
+
+    fn peek_token(&mut self) -> Option<Token<'a>> {
+        let chs = self.chs;
+        let token = self.next_token();
+        self.chs = chs;
+        token
+    }
+
+    fn next_token(&mut self) -> Option<Token<'a>> {
+        self.skip_space();
+        let mut chs = self.chs.chars();
+        let token = match chs.next()? {
+            '\\' => {
+                let rest = chs.as_str();
+                let len = rest.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(rest.len());
+                let len = if len == 0 { rest.chars().next().map_or(0, char::len_utf8) } else { len };
+                chs = rest[len..].chars();
+                Token::Command(&rest[..len])
+            }
+            c => Token::Char(c),
+        };
+        self.chs = chs.as_str();
+        Some(token)
+    }
+}
+
+fn node(xml: String) -> Node {
+    Node { xml, limits: false }
+}
+
+fn mrow(row: Vec<Node>) -> String {
+    if row.len() == 1 {
+        return row.into_iter().next().unwrap().xml;
+    }
+    format!("<mrow>{}</mrow>", row.into_iter().map(|node| node.xml).collect::<String>())
+}
+
+struct Escape<'a>(&'a str);
+
+impl<'a> std::fmt::Display for Escape<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '<' => write!(f, "&lt;")?,
+                '>' => write!(f, "&gt;")?,
+                '&' => write!(f, "&amp;")?,
+                c => write!(f, "{c}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+const FUNCTIONS: &[&str] = &[
+    "sin", "cos", "tan", "cot", "sec", "csc", "arcsin", "arccos", "arctan",
+    "sinh", "cosh", "tanh", "log", "ln", "lg", "exp", "deg", "dim", "ker", "hom", "arg",
+];
+
+const LIMIT_FUNCTIONS: &[&str] = &["lim", "limsup", "liminf", "max", "min", "sup", "inf", "det", "gcd", "Pr"];
+
+fn greek(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "alpha" => "α", "beta" => "β", "gamma" => "γ", "delta" => "δ", "epsilon" => "ϵ", "varepsilon" => "ε",
+        "zeta" => "ζ", "eta" => "η", "theta" => "θ", "vartheta" => "ϑ", "iota" => "ι", "kappa" => "κ",
+        "lambda" => "λ", "mu" => "μ", "nu" => "ν", "xi" => "ξ", "pi" => "π", "varpi" => "ϖ",
+        "rho" => "ρ", "varrho" => "ϱ", "sigma" => "σ", "varsigma" => "ς", "tau" => "τ", "upsilon" => "υ",
+        "phi" => "ϕ", "varphi" => "φ", "chi" => "χ", "psi" => "ψ", "omega" => "ω",
+        "Gamma" => "Γ", "Delta" => "Δ", "Theta" => "Θ", "Lambda" => "Λ", "Xi" => "Ξ", "Pi" => "Π",
+        "Sigma" => "Σ", "Upsilon" => "Υ", "Phi" => "Φ", "Psi" => "Ψ", "Omega" => "Ω",
+        _ => return None,
+    })
+}
+
+fn symbol(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "pm" => "±", "mp" => "∓", "times" => "×", "div" => "÷", "cdot" => "⋅", "ast" => "∗", "star" => "⋆",
+        "circ" => "∘", "bullet" => "∙", "otimes" => "⊗", "oplus" => "⊕", "dagger" => "†",
+        "leq" | "le" => "≤", "geq" | "ge" => "≥", "neq" | "ne" => "≠", "ll" => "≪", "gg" => "≫",
+        "approx" => "≈", "equiv" => "≡", "sim" => "∼", "simeq" => "≃", "cong" => "≅", "propto" => "∝",
+        "in" => "∈", "notin" => "∉", "ni" => "∋", "subset" => "⊂", "supset" => "⊃", "subseteq" => "⊆",
+        "supseteq" => "⊇", "cup" => "∪", "cap" => "∩", "setminus" => "∖",
+        "wedge" | "land" => "∧", "vee" | "lor" => "∨", "neg" | "lnot" => "¬",
+        "to" | "rightarrow" => "→", "leftarrow" | "gets" => "←", "leftrightarrow" => "↔",
+        "Rightarrow" => "⇒", "Leftarrow" => "⇐", "Leftrightarrow" => "⇔", "implies" => "⟹", "iff" => "⟺",
+        "mapsto" => "↦", "uparrow" => "↑", "downarrow" => "↓",
+        "forall" => "∀", "exists" => "∃", "ldots" | "dots" => "…", "cdots" => "⋯", "vdots" => "⋮", "ddots" => "⋱",
+        "langle" => "⟨", "rangle" => "⟩", "lfloor" => "⌊", "rfloor" => "⌋", "lceil" => "⌈", "rceil" => "⌉",
+        "mid" => "∣", "parallel" => "∥", "perp" => "⊥", "colon" => ":",
+        "vert" | "lvert" | "rvert" => "|", "Vert" | "lVert" | "rVert" | "|" => "‖",
+        "{" | "lbrace" => "{", "}" | "rbrace" => "}", "%" => "%", "#" => "#", "_" => "_", "&" => "&amp;",
+        _ => return None,
+    })
+}
+
+fn ordinary(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "infty" => "∞", "partial" => "∂", "nabla" => "∇", "emptyset" => "∅", "hbar" => "ℏ", "ell" => "ℓ",
+        "Re" => "ℜ", "Im" => "ℑ", "aleph" => "ℵ", "angle" => "∠", "prime" => "′",
+        _ => return None,
+    })
+}
+
+fn big_operator(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "sum" => "∑", "prod" => "∏", "coprod" => "∐", "bigcup" => "⋃", "bigcap" => "⋂",
+        "bigoplus" => "⨁", "bigotimes" => "⨂", "bigvee" => "⋁", "bigwedge" => "⋀",
+        _ => return None,
+    })
+}
+
+fn integral(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "int" => "∫", "iint" => "∬", "iiint" => "∭", "oint" => "∮",
+        _ => return None,
+    })
+}
+
+fn space(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "," => "0.1667em", ":" | ">" => "0.2222em", ";" => "0.2778em", "!" => "-0.1667em",
+        " " => "0.25em", "quad" => "1em", "qquad" => "2em",
+        _ => return None,
+    })
+}
+
+fn accent(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "hat" | "widehat" => "^", "bar" | "overline" => "¯", "vec" => "→", "tilde" | "widetilde" => "~",
+        "dot" => "˙", "ddot" => "¨",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inline(tex: &str) -> String {
+        to_mathml(tex, false).unwrap()
+    }
+
+    #[test]
+    fn scripts_and_fractions() {
+        assert_eq!(inline("x^2"), "<math><msup><mi>x</mi><mn>2</mn></msup></math>");
+        assert_eq!(inline("a_{i}^{n}"), "<math><msubsup><mi>a</mi><mi>i</mi><mi>n</mi></msubsup></math>");
+        assert_eq!(inline("f'"), "<math><msup><mi>f</mi><mo>&#x2032;</mo></msup></math>");
+        assert_eq!(inline("\\frac{1}{n}"), "<math><mfrac><mn>1</mn><mi>n</mi></mfrac></math>");
+        assert_eq!(inline("\\sqrt[3]{x}"), "<math><mroot><mi>x</mi><mn>3</mn></mroot></math>");
+        assert_eq!(inline("12.5 - x"), "<math><mrow><mn>12.5</mn><mo>&#x2212;</mo><mi>x</mi></mrow></math>");
+    }
+
+    #[test]
+    fn symbols_and_fonts() {
+        assert_eq!(inline("\\alpha \\Gamma"), "<math><mrow><mi>α</mi><mi mathvariant=\"normal\">Γ</mi></mrow></math>");
+        assert_eq!(inline("a \\leq b"), "<math><mrow><mi>a</mi><mo>≤</mo><mi>b</mi></mrow></math>");
+        assert_eq!(inline("\\sin x"), "<math><mrow><mi>sin</mi><mi>x</mi></mrow></math>");
+        assert_eq!(inline("\\mathbb{R}"), "<math><mi mathvariant=\"double-struck\">R</mi></math>");
+        assert_eq!(inline("\\text{a < b}"), "<math><mtext>a &lt; b</mtext></math>");
+        assert_eq!(inline("\\hat{x}"), "<math><mover accent=\"true\"><mi>x</mi><mo>^</mo></mover></math>");
+    }
+
+    #[test]
+    fn limits_in_display_style() {
+        let sum = "\\sum_{i=1}^n i";
+        assert_eq!(
+            to_mathml(sum, true).unwrap(),
+            "<math display=\"block\"><mrow><munderover><mo>∑</mo><mrow><mi>i</mi><mo>=</mo><mn>1</mn></mrow><mi>n</mi></munderover><mi>i</mi></mrow></math>"
+        );
+        assert!(inline(sum).contains("<msubsup><mo>∑</mo>"));
+        assert!(to_mathml("\\int_0^1", true).unwrap().contains("<msubsup><mo>∫</mo><mn>0</mn><mn>1</mn></msubsup>"));
+    }
+
+    #[test]
+    fn delimiters_and_environments() {
+        assert_eq!(
+            inline("\\left( x \\right."),
+            "<math><mrow><mo fence=\"true\">(</mo><mi>x</mi></mrow></math>"
+        );
+        assert_eq!(
+            inline("\\begin{pmatrix} a & b \\\\ c & d \\end{pmatrix}"),
+            "<math><mrow><mo fence=\"true\">(</mo><mtable><mtr><mtd><mi>a</mi></mtd><mtd><mi>b</mi></mtd></mtr><mtr><mtd><mi>c</mi></mtd><mtd><mi>d</mi></mtd></mtr></mtable><mo fence=\"true\">)</mo></mrow></math>"
+        );
+        assert!(inline("\\begin{cases} 1 & x > 0 \\end{cases}").contains("<mtable columnalign=\"left left\">"));
+    }
+
+    #[test]
+    fn unsupported_input() {
+        assert_eq!(to_mathml("\\foo", false), Err("unsupported command \\foo".to_string()));
+        assert_eq!(to_mathml("\\frac{1}", false), Err("missing argument".to_string()));
+        assert_eq!(to_mathml("{x", false), Err("\"}\" is expected".to_string()));
+        assert_eq!(to_mathml("x}", false), Err("unexpected \"}\"".to_string()));
+        assert_eq!(to_mathml("\\begin{tabular}", false), Err("unsupported environment tabular".to_string()));
+        assert_eq!(to_mathml("\\begin{matrix} a \\end{pmatrix}", false), Err("\\end{matrix} is expected".to_string()));
+        assert_eq!(to_mathml("\\mathbf{x+1}", false), Err("unsupported argument of \\mathbf".to_string()));
+    }
+}
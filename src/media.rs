@@ -0,0 +1,62 @@
+use std::borrow::Cow;
+
+use crate::data::MediaKind::{self, *};
+
+// media embedded by the extension of the path, with the MIME type
+const MEDIA_TYPES: &[(&str, MediaKind, &str)] = &[
+    ("png", Image, "image/png"),
+    ("jpg", Image, "image/jpeg"),
+    ("jpeg", Image, "image/jpeg"),
+    ("gif", Image, "image/gif"),
+    ("svg", Image, "image/svg+xml"),
+    ("webp", Image, "image/webp"),
+    ("avif", Image, "image/avif"),
+    ("bmp", Image, "image/bmp"),
+    ("ico", Image, "image/x-icon"),
+    ("mp4", Video, "video/mp4"),
+    ("m4v", Video, "video/mp4"),
+    ("webm", Video, "video/webm"),
+    ("ogv", Video, "video/ogg"),
+    ("mov", Video, "video/quicktime"),
+    ("mp3", Audio, "audio/mpeg"),
+    ("ogg", Audio, "audio/ogg"),
+    ("oga", Audio, "audio/ogg"),
+    ("opus", Audio, "audio/ogg"),
+    ("wav", Audio, "audio/wav"),
+    ("m4a", Audio, "audio/mp4"),
+    ("flac", Audio, "audio/flac"),
+    ("pdf", Document, "application/pdf"),
+];
+
+// players of video sites embedded by the pattern of the URL
+const FRAME_TYPES: &[(&[&str], &str)] = &[
+    (&["https://www.youtube.com/watch?v=", "https://youtube.com/watch?v=", "https://m.youtube.com/watch?v="], "https://www.youtube.com/embed/"),
+    (&["https://www.youtube.com/embed/", "https://youtube.com/embed/", "https://www.youtube.com/shorts/", "https://youtu.be/"], "https://www.youtube.com/embed/"),
+    (&["https://vimeo.com/", "https://www.vimeo.com/", "https://player.vimeo.com/video/"], "https://player.vimeo.com/video/"),
+];
+
+// the kind, the URL to embed and the MIME type of media, or `None` for web pages
+pub fn media_type(url: &str) -> Option<(MediaKind, Cow<'_, str>, &'static str)> {
+    for &(prefixes, embed) in FRAME_TYPES {
+        let Some(id) = prefixes.iter().find_map(|prefix| url.strip_prefix(prefix)) else {
+            continue;
+        };
+        let id = &id[..id.find(['?', '&', '#', '/']).unwrap_or(id.len())];
+        if !id.is_empty() {
+            return Some((Frame, format!("{embed}{id}").into(), ""));
+        }
+    }
+
+    let ext = extension(url)?.to_ascii_lowercase();
+    MEDIA_TYPES
+        .iter()
+        .find(|&&(e, _, _)| e == ext)
+        .map(|&(_, kind, mime)| (kind, url.into(), mime))
+}
+
+// the extension of the path ignoring the query and the fragment
+pub fn extension(url: &str) -> Option<&str> {
+    let path = &url[..url.find(['?', '#']).unwrap_or(url.len())];
+    let name = path.rsplit('/').next().unwrap_or(path);
+    name.rsplit_once('.').map(|(_, ext)| ext)
+}
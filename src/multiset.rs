@@ -15,4 +15,10 @@ impl<T: Ord> MultiSet<T> {
         self.0.insert(item, count + 1);
         count
     }
+}
+
+impl<T: Ord> Default for MultiSet<T> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
\ No newline at end of file
@@ -1,36 +1,147 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
 use regex::Regex;
 use reqwest::{self, header};
 use tokio;
 
+use crate::bib::{label, parse_bib};
+use crate::csv::{format_number, is_number, read_csv};
 use crate::data::*;
+use crate::imagesize::image_size;
+use crate::macros::{expand, parse_macro, read_macros};
+use crate::mathml::to_mathml;
+use crate::media::{extension, media_type};
 use crate::multiset::MultiSet;
+use crate::site::relative_url;
 use Block::*;
 use Span::*;
 use SyntaxError::*;
 
-pub fn parse(doc: &str) -> Result<(String, List, Vec<Block>), SyntaxError> {
-    let mut parser = Parser::new(doc);
+// the documents included by `@[include](path)`, read before parsing so that the parsed document can borrow them
+#[derive(Default)]
+pub struct Includes {
+    files: Vec<(PathBuf, PathBuf, String)>,
+}
+
+impl Includes {
+    pub fn read(path: &Path, src: &str) -> Self {
+        let mut includes = Includes::default();
+        includes.read_from(path, src);
+        includes
+    }
+
+    // files that cannot be read are reported when the parser reaches them
+    fn read_from(&mut self, path: &Path, src: &str) {
+        for line in src.lines() {
+            let Some((url, _)) = line.trim_start().strip_prefix("@[include](").and_then(|rest| rest.split_once(')')) else {
+                continue;
+            };
+            let file = path.parent().unwrap_or(Path::new("")).join(url.trim());
+            let canonical = fs::canonicalize(&file).unwrap_or(file.clone());
+            if self.get(&canonical).is_some() {
+                continue;
+            }
+            let Ok(src) = fs::read_to_string(&file) else {
+                continue;
+            };
+            // registered before its own includes to stop at cycles
+            self.files.push((canonical, file.clone(), src.clone()));
+            self.read_from(&file, &src);
+        }
+    }
+
+    fn get(&self, canonical: &Path) -> Option<(&Path, &str)> {
+        self.files.iter().find(|(c, _, _)| c == canonical).map(|(_, file, src)| (file.as_path(), src.as_str()))
+    }
+}
+
+pub fn parse<'a>(doc: &'a str, path: &'a Path, includes: &'a Includes, options: &[(&'a str, &'a str)]) -> Result<Document<'a>, SyntaxError> {
+    let mut parser = Parser::new(doc, path, includes);
+    parser.meta.extend(options.iter().copied());
+    // `--fetch=false` leaves link titles and link cards empty instead of requesting the pages
+    parser.fetch = !options.contains(&("fetch", "false"));
+    parser.includes.push(fs::canonicalize(path).unwrap_or(path.to_path_buf()));
     parser.parse_document()?;
-    return Ok((parser.title, parser.toc, parser.content));
+    Ok(Document {
+        title: parser.title,
+        toc: parser.toc,
+        content: parser.content,
+        macros: parser.macros,
+        meta: parser.meta,
+        backlinks: Vec::new(),
+    })
+}
+
+// the text of a named footnote, which may be defined in an included document, ending at `end` of `doc`
+#[derive(Clone, Copy)]
+struct Note<'a> {
+    doc: &'a str,
+    text: &'a str,
+    end: usize,
 }
 
 pub struct Parser<'a> {
+    // `chs` is the rest of `doc` or of a text in it ending at `end`
+    doc: &'a str,
     chs: &'a str,
+    end: usize,
+    path: &'a Path,
+    root: &'a Path,
+    sources: &'a Includes,
+    includes: Vec<PathBuf>,
     headers: MultiSet<String>,
-    notes: Vec<(Inline<'a>, usize)>,
+    notes: Vec<(Inline<'a>, usize, usize)>,
     note_id: usize,
+    footnotes: BTreeMap<&'a str, (Note<'a>, usize, usize)>,
+    link_defs: BTreeMap<String, (Cow<'a, str>, Option<&'a str>, bool)>,
+    bib: BTreeMap<String, BibEntry>,
+    bib_labels: BTreeMap<&'a str, String>,
+    cited: Vec<&'a str>,
+    eq_id: usize,
+    captions: MultiSet<&'static str>,
+    meta: BTreeMap<&'a str, &'a str>,
+    section: usize,
+    env_counters: BTreeMap<EnvKind, usize>,
+    labels: BTreeMap<&'a str, String>,
+    autolink: Regex,
+    in_link: bool,
+    fetch: bool,
+    macros: Vec<Macro>,
     title: String,
     toc: List<'a>,
     content: Vec<Block<'a>>,
 }
 
 impl<'a> Parser<'a> {
-    fn new(doc: &'a str) -> Self {
+    fn new(doc: &'a str, path: &'a Path, sources: &'a Includes) -> Self {
         Parser {
+            doc,
             chs: doc,
+            end: doc.len(),
+            path,
+            root: path,
+            sources,
+            includes: Vec::new(),
             headers: MultiSet::new(),
             notes: Vec::new(),
             note_id: 0,
+            footnotes: BTreeMap::new(),
+            link_defs: BTreeMap::new(),
+            bib: BTreeMap::new(),
+            bib_labels: BTreeMap::new(),
+            cited: Vec::new(),
+            eq_id: 0,
+            captions: MultiSet::new(),
+            meta: BTreeMap::new(),
+            section: 0,
+            env_counters: BTreeMap::new(),
+            labels: BTreeMap::new(),
+            autolink: Regex::new(r#"(?:https?://|www\.|mailto:)[\x21-\x7e&&[^<>"'|\[\]`\\]]+|[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)+"#).unwrap(),
+            in_link: false,
+            fetch: true,
+            macros: Vec::new(),
             title: String::new(),
             toc: List {
                 ordered: true,
@@ -41,22 +152,256 @@ impl<'a> Parser<'a> {
     }
 
     pub fn parse_document(&mut self) -> Result<(), SyntaxError> {
+        if self.starts_with_next("---\n") || self.starts_with_next("---\r\n") {
+            self.parse_front_matter()?;
+        }
+        self.read_shared_files()?;
+
+        self.scan_definitions();
+        let mut content = Vec::new();
         while !self.chs.is_empty() {
-            let block = self.parse_block()?;
-            match block {
-                Paragraph { text } if text.0.is_empty() => {}
-                _ => {
-                    self.content.push(block);
-                }
-            }
+            self.parse_blocks(&mut content)?;
         }
+        self.content = content;
 
         let refs = self.catch_refs();
         self.content.push(refs);
 
+        // named notes cited more than once link back to every citation
+        let mut cites = BTreeMap::new();
+        for (key, &(_, id, count)) in &self.footnotes {
+            if id == 0 {
+                println!("Warning: footnote \"{key}\" is defined but not used");
+            } else {
+                cites.insert(id, count);
+            }
+        }
+        count_cites(&mut self.content, &cites);
+        for (key, &(_, _, used)) in &self.link_defs {
+            if !used {
+                println!("Warning: link \"{key}\" is defined but not used");
+            }
+        }
+
+        self.resolve_refs()?;
+        if self.meta.get("footnotes") == Some(&"sidenote") {
+            self.fill_sidenotes();
+        }
+        Ok(())
+    }
+
+    // collect the definitions `[^key]: text` and `[key]: url "title"` outside code blocks
+    fn scan_definitions(&mut self) {
+        let mut in_code = false;
+        let mut offset = self.pos();
+        for line in self.chs.split_inclusive('\n') {
+            let start = offset;
+            offset += line.len();
+            let line = line.trim_end_matches(['\n', '\r']);
+            if line.trim_start().starts_with("```") {
+                in_code = !in_code;
+            } else if in_code {
+                continue;
+            } else if let Some((key, text)) = footnote_def(line) {
+                let note = Note { doc: self.doc, text, end: start + line.trim_end().len() };
+                self.footnotes.insert(key, (note, 0, 0));
+            } else if let Some((key, url, title)) = link_def(line) {
+                let url = self.rebase_url(url);
+                self.link_defs.entry(key.to_lowercase()).or_insert((url, title, false));
+            }
+        }
+    }
+
+    fn parse_front_matter(&mut self) -> Result<(), SyntaxError> {
+        loop {
+            let line = self.read_until_trim(&["\n", "\r\n"])?;
+            if line == "---" {
+                break;
+            }
+            if let Some((key, value)) = line.split_once(':') {
+                if let Some(mac) = parse_macro(key.trim(), value.trim()) {
+                    self.macros.push(mac);
+                } else {
+                    self.meta.insert(key.trim(), value.trim());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // files named in the front matter or by the options, with or without a front matter
+    fn read_shared_files(&mut self) -> Result<(), SyntaxError> {
+        if let Some(&path) = self.meta.get("macros") {
+            let path = self.resolve_path(path);
+            let Ok(src) = fs::read_to_string(&path) else {
+                return Err(NotFound(path.display().to_string()));
+            };
+            // definitions in the front matter take precedence over the shared file
+            let mut macros = read_macros(&src);
+            macros.retain(|mac| self.macros.iter().all(|m| m.name != mac.name));
+            self.macros.append(&mut macros);
+        }
+
+        if let Some(&path) = self.meta.get("bibliography") {
+            let path = self.resolve_path(path);
+            let Ok(src) = fs::read_to_string(&path) else {
+                return Err(NotFound(path.display().to_string()));
+            };
+            self.bib = parse_bib(&src);
+        }
         Ok(())
     }
 
+    fn line(&self) -> usize {
+        self.line_at(self.pos())
+    }
+
+    // counting lines takes time, so positions are kept until a line number is reported
+    fn pos(&self) -> usize {
+        self.end - self.chs.len()
+    }
+
+    // parse `text` of `doc` ending at `end`, such as the text of a footnote, and then the rest of the input
+    fn parse_slice<T>(&mut self, doc: &'a str, text: &'a str, end: usize, parse: impl FnOnce(&mut Self) -> Result<T, SyntaxError>) -> Result<T, SyntaxError> {
+        let saved = (self.doc, self.chs, self.end);
+        (self.doc, self.chs, self.end) = (doc, text, end);
+        let res = parse(self);
+        (self.doc, self.chs, self.end) = saved;
+        res
+    }
+
+    fn line_at(&self, pos: usize) -> usize {
+        self.doc[..pos].matches('\n').count() + 1
+    }
+
+    fn resolve_path(&self, path: &str) -> std::path::PathBuf {
+        self.path.parent().unwrap_or(Path::new("")).join(path)
+    }
+
+    // a relative URL in an included document as a URL relative to the document being converted
+    fn rebase_url(&self, url: &'a str) -> Cow<'a, str> {
+        let end = url.find(['?', '#']).unwrap_or(url.len());
+        let path = &url[..end];
+        if self.path == self.root || path.is_empty() || path.starts_with('/') || path.contains(':') || path.starts_with("www.") {
+            return url.into();
+        }
+        // `dir/..` cancel out
+        let mut segments: Vec<&str> = Vec::new();
+        let rebased = relative_url(self.root, &self.resolve_path(path));
+        for segment in rebased.split('/') {
+            match segments.last() {
+                Some(&last) if segment == ".." && last != ".." => {
+                    segments.pop();
+                }
+                _ => segments.push(segment),
+            }
+        }
+        let mut rebased = segments.join("/");
+        if path.ends_with('/') {
+            rebased.push('/');
+        }
+        rebased.push_str(&url[end..]);
+        rebased.into()
+    }
+
+    fn expand_math(&self, math: &'a str) -> std::borrow::Cow<'a, str> {
+        if self.meta.get("expand-macros") == Some(&"true") || self.meta.get("math") == Some(&"mathml") {
+            expand(math, &self.macros).into()
+        } else {
+            math.into()
+        }
+    }
+
+    // `pos` is the position of the math for warnings
+    fn render_math(&self, math: &str, display: bool, pos: usize) -> Option<String> {
+        if self.meta.get("math") != Some(&"mathml") {
+            return None;
+        }
+        match to_mathml(math, display) {
+            Ok(mathml) => Some(mathml),
+            Err(err) => {
+                println!("Warning: line {}: could not convert \"{}\" to MathML: {err}", self.line_at(pos), math.trim());
+                None
+            }
+        }
+    }
+
+    fn resolve_refs(&mut self) -> Result<(), SyntaxError> {
+        let labels = &self.labels;
+        let mut resolve = |span: &mut Span<'a>| {
+            if let CrossRef { label, text } = span {
+                let Some(name) = labels.get(label) else {
+                    return Err(UndefinedLabel(label.to_string()));
+                };
+                *text = name.clone();
+            }
+            Ok(())
+        };
+        self.toc.walk_spans_mut(&mut resolve)?;
+        self.content.iter_mut().try_for_each(|block| block.walk_spans_mut(&mut resolve))
+    }
+
+    // copy each note next to its first citation to show it in the margin
+    fn fill_sidenotes(&mut self) {
+        let mut notes = BTreeMap::new();
+        collect_notes(&self.content, &mut notes);
+        let mut fill = |span: &mut Span<'a>| -> Result<(), std::convert::Infallible> {
+            if let Cite { id, count: 1, note } = span {
+                *note = notes.get(id).cloned();
+            }
+            Ok(())
+        };
+        for block in &mut self.content {
+            let _ = block.walk_spans_mut(&mut fill);
+        }
+    }
+
+    // parse a block into `blocks`, splicing the blocks of included documents
+    fn parse_blocks(&mut self, blocks: &mut Vec<Block<'a>>) -> Result<(), SyntaxError> {
+        if self.starts_with_next("@[include](") {
+            let pos = self.pos();
+            return self.parse_include(blocks).map_err(|err| Line(self.line_at(pos), Box::new(err)));
+        }
+
+        match self.parse_block()? {
+            Paragraph { text } if text.0.is_empty() => {}
+            block => blocks.push(block),
+        }
+        Ok(())
+    }
+
+    fn parse_include(&mut self, blocks: &mut Vec<Block<'a>>) -> Result<(), SyntaxError> {
+        let url = self.read_until_trim(&[")"])?;
+        self.is_eol();
+
+        let file = self.resolve_path(url);
+        let canonical = fs::canonicalize(&file).unwrap_or(file.clone());
+        if self.includes.contains(&canonical) {
+            return Err(Cycle(file.display().to_string()));
+        }
+        let Some((path, src)) = self.sources.get(&canonical) else {
+            return Err(NotFound(file.display().to_string()));
+        };
+
+        let saved = (self.doc, self.chs, self.end, self.path);
+        (self.doc, self.chs, self.end, self.path) = (src, src, src.len(), path);
+        self.includes.push(canonical);
+
+        // the front matter of an included document is ignored
+        if self.starts_with_next("---\n") || self.starts_with_next("---\r\n") {
+            while !self.chs.is_empty() && self.read_until_trim(&["\n", "\r\n"])? != "---" {}
+        }
+        self.scan_definitions();
+        let mut res = Ok(());
+        while !self.chs.is_empty() && res.is_ok() {
+            res = self.parse_blocks(blocks);
+        }
+
+        self.includes.pop();
+        (self.doc, self.chs, self.end, self.path) = saved;
+        res.map_err(|err| File(path.display().to_string(), Box::new(err)))
+    }
+
     fn parse_block(&mut self) -> Result<Block<'a>, SyntaxError> {
         // header
         if self.starts_with_next("# ") {
@@ -108,11 +453,23 @@ impl<'a> Parser<'a> {
             return self.parse_code_block();
         }
 
+        // environment
+        if self.starts_with_next(":::") {
+            return self.parse_env();
+        }
+
         // reference
         if self.starts_with_next("[^]") {
             return Ok(self.catch_refs());
         }
 
+        // footnote or link definition
+        let line = self.chs.find('\n').map_or(self.chs, |i| &self.chs[..i + 1]);
+        if footnote_def(line).is_some() || link_def(line).is_some() {
+            self.chs = &self.chs[line.len()..];
+            return Ok(Paragraph { text: Inline(Vec::new()) });
+        }
+
         // paragraph
         self.parse_paragraph()
     }
@@ -120,31 +477,29 @@ impl<'a> Parser<'a> {
     fn parse_header(&mut self, level: u32) -> Result<Block<'a>, SyntaxError> {
         let header = self.parse_inline()?;
 
-        let mut header_toc = Vec::new();
-        for span in &header.0 {
-            match span {
-                Cite { .. } => {}
-                Link { text, .. } => {
-                    for span in &text.0 {
-                        header_toc.push(span.clone());
-                    }
-                }
-                _ => header_toc.push(span.clone()),
-            }
-        }
+        let header_toc = strip_toc(&header.0);
 
         let mut header_id = String::new();
         for span in &header_toc {
             match span {
-                Math { math } => header_id.push_str(math),
+                Math { math, .. } => header_id.push_str(math),
                 Code { code } => header_id.push_str(code),
                 Text { text } => header_id.push_str(text),
+                Strike { text } | Mark { text } | Underline { text } | Sup { text } | Sub { text } => header_id.push_str(&text.plain_text()),
+                Kbd { key } => header_id.push_str(key),
                 _ => {}
             }
         }
 
+        if level == 2 {
+            self.section += 1;
+            if self.meta.get("theorem-within") == Some(&"section") {
+                self.env_counters.clear();
+            }
+        }
+
         // modify title or table of contents
-        if level == 1 {
+        if level == 1 && self.includes.len() <= 1 {
             self.title = header_id.clone();
         } else {
             let count = self.headers.insert(header_id.clone());
@@ -160,6 +515,7 @@ impl<'a> Parser<'a> {
                 item: Inline(vec![Link {
                     text: Inline(header_toc),
                     url: format!("#{}", &header_id).into(),
+                    title: None,
                 }]),
                 list: List {
                     ordered: true,
@@ -216,17 +572,42 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_embed(&mut self) -> Result<Block<'a>, SyntaxError> {
+        if self.starts_with_next("code](") {
+            let pos = self.pos();
+            return self.parse_code_include().map_err(|err| Line(self.line_at(pos), Box::new(err)));
+        }
+
         let text = self.parse_until_trim(Self::parse_cite, &["]("])?;
         let url = self.read_until_trim(&[")"])?;
+        let attrs = self.parse_attrs();
 
-        if url.ends_with(".png") || url.ends_with(".jpg") {
-            let title = Inline(text);
-            Ok(Image { title, url })
-        } else if url.ends_with(".mp4") {
-            let title = Inline(text);
-            Ok(Video { title, url })
+        // only local files are imported as tables
+        let ext = extension(url).map(str::to_ascii_lowercase);
+        if matches!(ext.as_deref(), Some("csv" | "tsv")) && !url.contains("://") {
+            let pos = self.pos();
+            self.is_eol();
+            return self.parse_csv_table(text, url, attrs).map_err(|err| Line(self.line_at(pos), Box::new(err)));
+        }
+        if let Some((kind, url, mime)) = media_type(url) {
+            let text = Inline(text);
+            let mut media_attrs = MediaAttrs {
+                alt: attrs.get("alt").map_or_else(|| text.plain_text(), str::to_string),
+                width: attrs.get("width").map(Into::into),
+                height: attrs.get("height").map(Into::into),
+                classes: attrs.classes.clone(),
+                loading: attrs.get("loading").unwrap_or("lazy"),
+            };
+            let url = match url {
+                Cow::Borrowed(url) => self.rebase_url(url),
+                url => url,
+            };
+            if kind == MediaKind::Image && !url.contains("://") && !url.starts_with("//") && !url.starts_with("data:") {
+                self.size_image(&url, &mut media_attrs);
+            }
+            let caption = self.make_caption("Figure", text, attrs);
+            Ok(Media { kind, url, mime, attrs: media_attrs, caption })
         } else {
-            let (title, image, description, site_name) = get_ogp_info(&url);
+            let (title, image, description, site_name) = if self.fetch { get_ogp_info(url) } else { (String::new(), None, None, None) };
             Ok(LinkCard {
                 title,
                 image,
@@ -237,23 +618,211 @@ impl<'a> Parser<'a> {
         }
     }
 
+    fn parse_code_include(&mut self) -> Result<Block<'a>, SyntaxError> {
+        let url = self.read_until_trim(&[")"])?;
+        let attrs = self.parse_attrs();
+        let (path, fragment) = url.split_once('#').unwrap_or((url, ""));
+
+        let file = self.resolve_path(path);
+        let Ok(src) = fs::read_to_string(&file) else {
+            return Err(NotFound(file.display().to_string()));
+        };
+        let (first, lines) = select_lines(&src, fragment).ok_or_else(|| BadRange(format!("\"{fragment}\" in \"{path}\"")))?;
+
+        let ext = Path::new(path).extension().and_then(|ext| ext.to_str()).unwrap_or("");
+        let lang = attrs.get("lang").unwrap_or(match ext {
+            "rs" => "rust",
+            "py" => "python",
+            "sh" | "bash" => "shell",
+            "js" | "mjs" => "javascript",
+            "ts" => "typescript",
+            "md" => "markdown",
+            "yml" => "yaml",
+            ext => ext,
+        });
+
+        // line numbers start from the first included line unless specified
+        let from_first = attrs.get("linenos") == Some("");
+        let mut code_attrs = parse_code_attrs(attrs)?;
+        if from_first {
+            code_attrs.linenos = Some(first);
+        }
+
+        // backslashes are escape characters in the output of code blocks
+        let mut code = String::new();
+        for line in lines {
+            code.push_str(&line.replace('\\', "\\\\"));
+            code.push('\n');
+        }
+        let caption = self.parse_caption("Listing")?;
+        Ok(CodeBlock { lang, code: code.into(), attrs: code_attrs, caption })
+    }
+
+    // the intrinsic size of a local image keeping the aspect ratio of a given width or height,
+    // where `url` is relative to the document being converted
+    fn size_image(&self, url: &str, attrs: &mut MediaAttrs<'a>) {
+        let file = self.root.parent().unwrap_or(Path::new("")).join(&url[..url.find(['?', '#']).unwrap_or(url.len())]);
+        let Ok(data) = fs::read(&file) else {
+            println!("Warning: line {}: image \"{}\" does not exist", self.line(), file.display());
+            return;
+        };
+        let Some((width, height)) = image_size(&data).filter(|&(width, height)| width > 0 && height > 0) else {
+            return;
+        };
+        let given = |size: &Option<std::borrow::Cow<str>>| size.as_deref().and_then(|size| size.parse::<u32>().ok());
+        // the other side keeping the aspect ratio, computed in 64 bits and reported if it is too large
+        let scale = |given: u32, num: u32, den: u32| {
+            let size = u32::try_from(given as u64 * num as u64 / den as u64).ok();
+            if size.is_none() {
+                println!("Warning: line {}: the size of image \"{}\" is too large", self.line(), file.display());
+            }
+            size.map(|size| size.to_string().into())
+        };
+        match (&attrs.width, &attrs.height) {
+            (None, None) => {
+                attrs.width = Some(width.to_string().into());
+                attrs.height = Some(height.to_string().into());
+            }
+            (Some(_), None) => {
+                if let Some(given) = given(&attrs.width) {
+                    attrs.height = scale(given, height, width);
+                }
+            }
+            (None, Some(_)) => {
+                if let Some(given) = given(&attrs.height) {
+                    attrs.width = scale(given, width, height);
+                }
+            }
+            (Some(_), Some(_)) => {}
+        }
+    }
+
+    fn parse_csv_table(&mut self, text: Vec<Span<'a>>, url: &'a str, attrs: Attrs<'a>) -> Result<Block<'a>, SyntaxError> {
+        let file = self.resolve_path(url);
+        let Ok(src) = fs::read_to_string(&file) else {
+            return Err(NotFound(file.display().to_string()));
+        };
+        let tsv = extension(url).is_some_and(|ext| ext.eq_ignore_ascii_case("tsv"));
+        let mut records = read_csv(&src, if tsv { '\t' } else { ',' });
+        let head = if attrs.get("header") == Some("false") || records.is_empty() {
+            None
+        } else {
+            Some(records.remove(0))
+        };
+
+        let width = head.iter().chain(records.iter()).map(Vec::len).max().unwrap_or(0);
+        let expected = head.as_ref().or(records.first()).map_or(0, Vec::len);
+        for (i, record) in records.iter().enumerate() {
+            if record.len() != expected {
+                println!("Warning: \"{url}\" record {} has {} fields but {expected} are expected", i + 1, record.len());
+            }
+        }
+
+        // columns selected by names in the header or by numbers from 1
+        let columns: Vec<usize> = match attrs.get("columns") {
+            Some(columns) => columns
+                .split(',')
+                .map(|col| {
+                    let col = col.trim();
+                    head.as_ref()
+                        .and_then(|head| head.iter().position(|name| name.trim() == col))
+                        .or(col.parse::<usize>().ok().filter(|&i| 0 < i && i <= width).map(|i| i - 1))
+                        .ok_or_else(|| BadRange(format!("column \"{col}\" in \"{url}\"")))
+                })
+                .collect::<Result<_, _>>()?,
+            None => (0..width).collect(),
+        };
+
+        let decimals = attrs.get("decimals").and_then(|decimals| decimals.parse().ok());
+        let thousands = attrs.get("thousands").map(|sep| if sep.is_empty() { "," } else { sep });
+
+        // columns of numbers are aligned to the right
+        let aligns: Vec<Option<Align>> = columns
+            .iter()
+            .map(|&col| {
+                let mut fields = records.iter().map(|record| field(record, col)).filter(|field| !field.is_empty()).peekable();
+                (fields.peek().is_some() && fields.all(is_number)).then_some(Align::Right)
+            })
+            .collect();
+        let row = |record: &Vec<String>, numbers: bool| -> Vec<Cell<'a>> {
+            columns
+                .iter()
+                .zip(&aligns)
+                .map(|(&col, &align)| {
+                    let field = field(record, col);
+                    let field = if numbers { format_number(field, decimals, thousands) } else { field.to_string() };
+                    // backslashes are escape characters in the output of text
+                    let text = if field.is_empty() { Vec::new() } else { vec![Text { text: field.replace('\\', "\\\\").into() }] };
+                    Cell { text: Inline(text), colspan: 1, rowspan: 1, align }
+                })
+                .collect()
+        };
+
+        let head = head.iter().map(|record| row(record, false)).collect();
+        let body = records.iter().map(|record| row(record, true)).collect();
+        let caption = self.make_caption("Table", Inline(text), attrs);
+        let caption = Some(caption).filter(|caption| caption.number.is_some());
+        Ok(Table { head, body, caption })
+    }
+
     fn parse_table(&mut self) -> Result<Block<'a>, SyntaxError> {
         let mut head = Vec::new();
+        let mut aligns = None;
+        while aligns.is_none() {
+            aligns = self.parse_table_sep();
+            if aligns.is_none() {
+                let Some(row) = self.parse_table_row()? else {
+                    break;
+                };
+                head.push(row);
+            }
+        }
+        // the body, which may have rows such as `| - |`, ends at a line starting with `-`
         let mut body = Vec::new();
-        while let Some(row) = self.parse_table_row()? {
-            head.push(row);
+        if aligns.is_some() {
+            while let Some(row) = self.parse_table_row()? {
+                body.push(row);
+            }
+            if self.starts_with_next("-") {
+                self.read_until_trim(&["\n", "\r\n"])?;
+            }
         }
-        while let Some(row) = self.parse_table_row()? {
-            body.push(row);
+
+        // rows are expected to have as many columns as the first one
+        let aligns = aligns.unwrap_or_default();
+        let columns = head.first().or(body.first()).map_or(0, |(_, row)| row.len());
+        for (pos, row) in head.iter().chain(body.iter()) {
+            if row.len() != columns {
+                println!("Warning: line {}: the table row has {} columns but {columns} are expected", self.line_at(*pos), row.len());
+            }
         }
-        Ok(Table { head, body })
+
+        let head = merge_cells(head, &aligns);
+        let body = merge_cells(body, &aligns);
+        let caption = self.parse_caption("Table")?;
+        Ok(Table { head, body, caption })
     }
 
-    fn parse_table_row(&mut self) -> Result<Option<Vec<Inline<'a>>>, SyntaxError> {
-        if self.starts_with_next("-") {
-            self.read_until_trim(&["\n", "\r\n"])?;
-            return Ok(None);
+    // a separator line such as `-----` or `|:--|:-:|--:|` with the alignment of each column
+    fn parse_table_sep(&mut self) -> Option<Vec<Option<Align>>> {
+        let line = self.chs.find('\n').map_or(self.chs, |i| &self.chs[..i + 1]);
+        let sep = line.trim();
+        if !sep.contains('-') || !sep.chars().all(|c| matches!(c, '-' | ':' | '|' | ' ' | '\t')) {
+            return None;
         }
+        self.chs = &self.chs[line.len()..];
+
+        let aligns = sep.split(|c: char| c == '|' || c.is_whitespace()).filter(|col| !col.is_empty());
+        Some(aligns.map(|col| match (col.starts_with(':'), col.ends_with(':')) {
+            (true, true) => Some(Align::Center),
+            (true, false) => Some(Align::Left),
+            (false, true) => Some(Align::Right),
+            (false, false) => None,
+        }).collect())
+    }
+
+    fn parse_table_row(&mut self) -> Result<Option<(usize, Vec<Inline<'a>>)>, SyntaxError> {
+        let pos = self.pos();
         if !self.starts_with_next("|") {
             return Ok(None);
         }
@@ -263,18 +832,116 @@ impl<'a> Parser<'a> {
             let data = Inline(self.parse_until_trim(Self::parse_cite, &["|"])?);
             row.push(data);
         }
-        Ok(Some(row))
+        Ok(Some((pos, row)))
     }
 
     fn parse_math_block(&mut self) -> Result<Block<'a>, SyntaxError> {
+        let pos = self.pos();
         let math = self.read_until_trim(&["$$"])?;
-        Ok(MathBlock { math })
+        let math = self.expand_math(math);
+        let mathml = self.render_math(&math, true, pos);
+        let attrs = self.parse_attrs();
+
+        self.eq_id += 1;
+        let number = self.eq_id;
+        let id = match attrs.id {
+            Some(label) => {
+                self.labels.insert(label, format!("({number})"));
+                label.to_string()
+            }
+            None => format!("eq-{number}"),
+        };
+        Ok(MathBlock { math, mathml, number, id })
     }
 
     fn parse_code_block(&mut self) -> Result<Block<'a>, SyntaxError> {
-        let lang = self.read_until_trim(&["\n", "\r\n"])?;
-        let code = self.read_until_trim(&["```"])?;
-        Ok(CodeBlock { lang, code })
+        let info = self.read_until_trim(&["\n", "\r\n"])?.trim();
+        let (lang, attrs) = info.split_once(' ').unwrap_or((info, ""));
+        let attrs = parse_code_attrs(Attrs::parse(attrs))?;
+        let code = self.read_until_trim(&["```"])?.into();
+        let caption = self.parse_caption("Listing")?;
+        Ok(CodeBlock { lang, code, attrs, caption })
+    }
+
+    fn parse_env(&mut self) -> Result<Block<'a>, SyntaxError> {
+        let pos = self.pos();
+        // `:::theorem` or `::: theorem`
+        let raw = self.read_until_trim(&["\n", "\r\n"])?;
+        let (line, attrs) = split_attrs(raw.trim());
+        let (name, title) = line.split_once([' ', '\t']).unwrap_or((line, ""));
+        // the title ends with the line before the attributes
+        let end = pos + raw.len() - raw.trim_start().len() + line.trim_end().len();
+        let Some(kind) = EnvKind::from_name(name) else {
+            return Err(Line(self.line_at(pos), Box::new(Expect(&["theorem", "lemma", "definition", "example", "proof"]))));
+        };
+
+        let title = if title.trim().is_empty() {
+            None
+        } else {
+            Some(self.parse_inline_str(title.trim(), end)?)
+        };
+
+        let number = if kind == EnvKind::Proof {
+            None
+        } else {
+            let counter = if self.meta.get("theorem-counter") == Some(&"shared") { EnvKind::Theorem } else { kind };
+            let count = self.env_counters.entry(counter).or_insert(0);
+            *count += 1;
+            Some(if self.meta.get("theorem-within") == Some(&"section") {
+                format!("{}.{}", self.section, count)
+            } else {
+                count.to_string()
+            })
+        };
+
+        if let (Some(id), Some(number)) = (attrs.id, &number) {
+            self.labels.insert(id, format!("{} {}", kind.name(), number));
+        }
+
+        let mut content = Vec::new();
+        while !self.chs.is_empty() {
+            let rest = self.chs.trim_start_matches([' ', '\t']);
+            if let Some(rest) = rest.strip_prefix(":::") {
+                let rest = rest.trim_start_matches([' ', '\t']);
+                if rest.is_empty() || rest.starts_with('\n') || rest.starts_with("\r\n") {
+                    self.chs = rest;
+                    self.is_eol();
+                    return Ok(Env { kind, title, number, id: attrs.id, content });
+                }
+            }
+
+            self.parse_blocks(&mut content)?;
+        }
+
+        Err(Expect(&[":::"]))
+    }
+
+    // caption line `: caption {#label}` following a table or a code block
+    fn parse_caption(&mut self, name: &'static str) -> Result<Option<Caption<'a>>, SyntaxError> {
+        let rest = self.chs.strip_prefix('\n').or(self.chs.strip_prefix("\r\n")).unwrap_or(self.chs);
+        let Some(rest) = rest.strip_prefix(": ") else {
+            return Ok(None);
+        };
+        self.chs = rest;
+
+        let pos = self.pos();
+        let line = self.read_until(&["\n", "\r\n"]);
+        self.is_eol();
+        let (line, attrs) = split_attrs(line);
+        let text = self.parse_inline_str(line.trim(), pos + line.trim_end().len())?;
+        Ok(Some(self.make_caption(name, text, attrs)))
+    }
+
+    fn make_caption(&mut self, name: &'static str, text: Inline<'a>, attrs: Attrs<'a>) -> Caption<'a> {
+        let number = if text.0.is_empty() && attrs.id.is_none() {
+            None
+        } else {
+            Some(self.captions.insert(name) + 1)
+        };
+        if let (Some(id), Some(number)) = (attrs.id, number) {
+            self.labels.insert(id, format!("{name} {number}"));
+        }
+        Caption { name, text, number, id: attrs.id }
     }
 
     fn parse_paragraph(&mut self) -> Result<Block<'a>, SyntaxError> {
@@ -286,7 +953,34 @@ impl<'a> Parser<'a> {
         let mut refs = Vec::new();
         refs.append(&mut self.notes);
 
-        Ref(refs)
+        // entries of the bibliography in the order of citation or of labels
+        let mut bib = Vec::new();
+        for key in self.cited.drain(..) {
+            bib.push((key, self.bib_labels[key].clone(), self.bib[key].clone()));
+        }
+        if self.meta.get("citation-style") == Some(&"author-year") {
+            bib.sort_by(|a, b| a.1.cmp(&b.1));
+        }
+
+        Ref(refs, bib)
+    }
+
+    // attributes `{...}` following a block on the same line
+    fn parse_attrs(&mut self) -> Attrs<'a> {
+        let rest = self.chs.trim_start_matches([' ', '\t']);
+        if rest.starts_with('{') && !rest.starts_with("{@") {
+            let line = rest.find('\n').map_or(rest, |i| &rest[..i]).trim_end();
+            if let Some(attrs) = line.strip_prefix('{').and_then(|line| line.strip_suffix('}')) {
+                self.chs = &rest[line.len()..];
+                return Attrs::parse(attrs);
+            }
+        }
+        Attrs::default()
+    }
+
+    // `text` ends at `end` of the document
+    fn parse_inline_str(&mut self, text: &'a str, end: usize) -> Result<Inline<'a>, SyntaxError> {
+        self.parse_slice(self.doc, text, end, Self::parse_inline)
     }
 
     fn parse_inline(&mut self) -> Result<Inline<'a>, SyntaxError> {
@@ -298,34 +992,130 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_cite(&mut self) -> Result<Span<'a>, SyntaxError> {
+        if let Some(link) = self.delimited_by("[[", "]]") {
+            return Ok(wiki_link(link));
+        }
+        if self.is_citation() {
+            self.chs = &self.chs[2..];
+            return self.parse_citation();
+        }
         if self.starts_with_next("[^") {
+            let key = self.chs.find(']').map_or("", |i| &self.chs[..i]);
+            if let Some(&(note, id, count)) = self.footnotes.get(key) {
+                self.chs = &self.chs[key.len() + 1..];
+                let id = if id == 0 {
+                    self.note_id += 1;
+                    let text = self.parse_note(note)?;
+                    self.notes.push((text, self.note_id, 1));
+                    self.note_id
+                } else {
+                    id
+                };
+                self.footnotes.insert(key, (note, id, count + 1));
+                return Ok(Cite { id, count: count + 1, note: None });
+            }
+            if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+                println!("Warning: footnote \"{key}\" is not defined");
+            }
+
             self.note_id += 1;
             let note = Inline(self.parse_until_trim(Self::parse_link, &["]"])?);
             let id = self.note_id;
 
-            self.notes.push((note, id));
+            self.notes.push((note, id, 1));
 
-            Ok(Cite { id })
+            Ok(Cite { id, count: 1, note: None })
         } else {
             self.parse_link()
         }
     }
 
+    // `[@...]` is a citation only with a bibliography and not followed by a link destination
+    fn is_citation(&self) -> bool {
+        if !self.chs.starts_with("[@") || !self.meta.contains_key("bibliography") {
+            return false;
+        }
+        self.chs.find(']').is_some_and(|end| !self.chs[end + 1..].starts_with(['(', '[']))
+    }
+
+    // `[@key]` or `[@key1; @key2]`
+    fn parse_citation(&mut self) -> Result<Span<'a>, SyntaxError> {
+        let numeric = self.meta.get("citation-style") != Some(&"author-year");
+        let pos = self.pos();
+        let mut items = Vec::new();
+        for key in self.read_until_trim(&["]"])?.split(';') {
+            let key = key.trim().trim_start_matches('@');
+            let Some(entry) = self.bib.get(key) else {
+                return Err(Line(self.line_at(pos), Box::new(UndefinedCitation(key.to_string()))));
+            };
+            if !self.bib_labels.contains_key(key) {
+                let label = if numeric { (self.bib_labels.len() + 1).to_string() } else { label(entry) };
+                self.bib_labels.insert(key, label);
+                self.cited.push(key);
+            }
+            items.push((key, self.bib_labels[key].clone()));
+        }
+        Ok(Citation { items, numeric })
+    }
+
+    fn parse_note(&mut self, note: Note<'a>) -> Result<Inline<'a>, SyntaxError> {
+        self.parse_slice(note.doc, note.text, note.end, |parser| {
+            let mut note = Vec::new();
+            while !parser.is_eol() {
+                note.push(parser.parse_link()?);
+            }
+            Ok(Inline(note))
+        })
+    }
+
     fn parse_link(&mut self) -> Result<Span<'a>, SyntaxError> {
-        if self.starts_with_next("[") {
-            // link
-            let text = self.parse_until_trim(Self::parse_emph, &["]("])?;
-            let url: std::borrow::Cow<'a, str> = self.read_until_trim(&[")"])?.into();
-
-            let text = if text.is_empty() {
-                Inline(vec![Text {
-                    text: get_title(url.as_ref()).into(),
-                }])
-            } else {
-                Inline(text)
+        if self.chs.starts_with("[") {
+            let start = self.chs;
+            self.chs = &self.chs[1..];
+            self.in_link = true;
+            let text = self.parse_until_trim(Self::parse_emph, &["]"]);
+            self.in_link = false;
+            let Ok(text) = text else {
+                self.chs = &start[1..];
+                return Ok(Text { text: "[".into() });
+            };
+            let label = &start[1..start.len() - self.chs.len() - 1];
+
+            // link `[text](url "title")`
+            if self.starts_with_next("(") {
+                let url = self.read_until_trim(&[")"])?;
+                let (url, title) = split_title(url);
+                let text = if text.is_empty() {
+                    Inline(vec![Text {
+                        text: if self.fetch { get_title(url).into() } else { String::new().into() },
+                    }])
+                } else {
+                    Inline(text)
+                };
+                return Ok(Link { text, url: self.rebase_url(url), title });
+            }
+
+            // reference link `[text][key]`, `[text][]` or `[key]`
+            let key = match self.chs.strip_prefix('[').and_then(|rest| rest.split_once(']')) {
+                Some((key, rest)) if !key.contains('\n') => {
+                    self.chs = rest;
+                    Some(if key.is_empty() { label } else { key })
+                }
+                _ => None,
             };
+            let explicit = key.is_some();
+            let key = key.unwrap_or(label).to_lowercase();
+            if let Some((url, title, used)) = self.link_defs.get_mut(&key) {
+                *used = true;
+                return Ok(Link { text: Inline(text), url: url.clone(), title: *title });
+            }
+            if explicit {
+                println!("Warning: line {}: link \"{key}\" is not defined", self.line());
+            }
 
-            Ok(Link { text, url })
+            // brackets which do not make a link are text
+            self.chs = &start[1..];
+            Ok(Text { text: "[".into() })
         } else {
             self.parse_emph()
         }
@@ -338,16 +1128,67 @@ impl<'a> Parser<'a> {
         } else if self.starts_with_next("__") {
             let text = Inline(self.parse_until_trim(Self::parse_emph, &["__"])?);
             Ok(Ital { text })
+        } else if let Some(text) = self.delimited("~~", true) {
+            Ok(Strike { text: self.parse_emph_str(text, "~~")? })
+        } else if let Some(text) = self.delimited("==", true) {
+            Ok(Mark { text: self.parse_emph_str(text, "==")? })
+        } else if let Some(text) = self.delimited("++", true) {
+            Ok(Underline { text: self.parse_emph_str(text, "++")? })
+        } else if let Some(text) = self.delimited("^", false) {
+            Ok(Sup { text: self.parse_emph_str(text, "^")? })
+        } else if let Some(text) = self.delimited("~", false) {
+            Ok(Sub { text: self.parse_emph_str(text, "~")? })
+        } else if let Some(key) = self.delimited_by("{{", "}}") {
+            Ok(Kbd { key })
         } else {
             self.parse_primary()
         }
     }
 
+    // the text between `marker`s on the same line, not surrounded by spaces or without spaces at all
+    fn delimited(&mut self, marker: &str, spaces: bool) -> Option<&'a str> {
+        let rest = self.chs.strip_prefix(marker)?;
+        let line = &rest[..rest.find('\n').unwrap_or(rest.len())];
+        let text = &line[..line.find(marker)?];
+        let valid = if spaces {
+            !text.starts_with(char::is_whitespace) && !text.ends_with(char::is_whitespace)
+        } else {
+            !text.contains(char::is_whitespace)
+        };
+        if text.is_empty() || !valid {
+            return None;
+        }
+        self.chs = &rest[text.len() + marker.len()..];
+        Some(text)
+    }
+
+    // the text between `open` and `close` on the same line
+    fn delimited_by(&mut self, open: &str, close: &str) -> Option<&'a str> {
+        let rest = self.chs.strip_prefix(open)?;
+        let text = &rest[..rest.find(close).filter(|&end| !rest[..end].contains('\n'))?];
+        self.chs = &rest[text.len() + close.len()..];
+        Some(text)
+    }
+
+    // `text` is followed by the closing `marker` just read
+    fn parse_emph_str(&mut self, text: &'a str, marker: &str) -> Result<Inline<'a>, SyntaxError> {
+        self.parse_slice(self.doc, text, self.pos() - marker.len(), |parser| {
+            let mut res = Vec::new();
+            while !parser.chs.is_empty() {
+                res.push(parser.parse_emph()?);
+            }
+            Ok(Inline(res))
+        })
+    }
+
     fn parse_primary(&mut self) -> Result<Span<'a>, SyntaxError> {
         // math
         if self.starts_with_next("$") {
+            let pos = self.pos();
             let math = self.read_until_trim(&["$"])?;
-            return Ok(Math { math });
+            let math = self.expand_math(math);
+            let mathml = self.render_math(&math, false, pos);
+            return Ok(Math { math, mathml });
         }
 
         // code
@@ -356,17 +1197,113 @@ impl<'a> Parser<'a> {
             return Ok(Code { code });
         }
 
+        // image, whose `](` and `)` close on the same line; otherwise `!` is text before a link or a note
+        if self.chs.starts_with("![") {
+            let line = &self.chs[..self.chs.find('\n').unwrap_or(self.chs.len())];
+            if !line.find("](").is_some_and(|i| line[i + 2..].contains(')')) {
+                self.chs = &self.chs[1..];
+                return Ok(Text { text: "!".into() });
+            }
+        }
+        if self.starts_with_next("![") {
+            let alt = self.read_until_trim(&["]("])?;
+            let url = self.read_until_trim(&[")"])?;
+            let mut attrs = Attrs::default();
+            if let Some(rest) = self.chs.strip_prefix('{').filter(|rest| !rest.starts_with('@')) {
+                if let Some(end) = rest.find('}').filter(|&end| !rest[..end].contains('\n')) {
+                    attrs = Attrs::parse(&rest[..end]);
+                    self.chs = &rest[end + 1..];
+                }
+            }
+            let mut attrs = MediaAttrs {
+                alt: attrs.get("alt").unwrap_or(alt).to_string(),
+                width: attrs.get("width").map(Into::into),
+                height: attrs.get("height").map(Into::into),
+                loading: attrs.get("loading").unwrap_or("lazy"),
+                classes: attrs.classes,
+            };
+            let url = self.rebase_url(url);
+            if !url.contains("://") && !url.starts_with("//") && !url.starts_with("data:") {
+                self.size_image(&url, &mut attrs);
+            }
+            return Ok(Image { url, attrs });
+        }
+
+        // cross reference
+        if self.starts_with_next("{@") {
+            let label = self.read_until_trim(&["}"])?;
+            return Ok(CrossRef { label, text: String::new() });
+        }
+
         // text
-        let text: std::borrow::Cow<'a, str> = self
-            .read_until(&["|", "**", "__", "[", "]", "$", "`", "\n", "\r\n"])
-            .into();
+        let start = self.chs;
+        let text = self.read_until(&["|", "**", "__", "~", "==", "++", "^", "{{", "![", "[", "]", "$", "`", "{@", "\n", "\r\n"]);
+
+        // bare URLs and email addresses, unless escaped by `\`, are links outside links and text inside them
+        let autolink = self.autolink.find_iter(text).find(|m| {
+            let prev = text[..m.start()].chars().next_back();
+            !prev.is_some_and(|c| c.is_ascii_alphanumeric() || c == '\\')
+        });
+        if let Some(m) = autolink {
+            self.chs = &start[m.start()..];
+            if m.start() > 0 {
+                return Ok(Text { text: text[..m.start()].into() });
+            }
+            if self.in_link {
+                let (text, rest) = self.chs.split_at(self.autolink_len());
+                self.chs = rest;
+                return Ok(Text { text: text.into() });
+            }
+            return Ok(self.parse_autolink());
+        }
         if !text.is_empty() {
-            return Ok(Text { text });
+            return Ok(Text { text: text.into() });
+        }
+
+        // markers which do not start a span are text
+        for marker in ["~~", "~", "==", "++", "^", "{{", "]"] {
+            if self.starts_with_next(marker) {
+                return Ok(Text { text: marker.into() });
+            }
         }
 
         Err(Empty)
     }
 
+    fn parse_autolink(&mut self) -> Span<'a> {
+        let (link, rest) = self.chs.split_at(self.autolink_len());
+        self.chs = rest;
+
+        let url = if link.starts_with("www.") {
+            format!("https://{link}").into()
+        } else if !link.contains(':') {
+            format!("mailto:{link}").into()
+        } else {
+            link.into()
+        };
+        let text = link.strip_prefix("mailto:").unwrap_or(link);
+        Link { text: Inline(vec![Text { text: text.into() }]), url, title: None }
+    }
+
+    // the length of the URL or the email address at the start
+    fn autolink_len(&self) -> usize {
+        let line = &self.chs[..self.chs.find(['\n', '\r']).unwrap_or(self.chs.len())];
+        let mut link = self.autolink.find(line).map_or("", |m| m.as_str());
+
+        // punctuation and unbalanced parentheses at the end are not a part of the URL;
+        // `_`, `*` and `~` are kept so that the rest of the URL is never read as emphasis
+        loop {
+            if let Some(rest) = link.strip_suffix(['.', ',', ':', ';', '!', '?', '\'', '"']) {
+                link = rest;
+            } else if link.ends_with(')') && link.matches(')').count() > link.matches('(').count() {
+                link = &link[..link.len() - 1];
+            } else {
+                break;
+            }
+        }
+        link.len()
+    }
+
     fn read_until(&mut self, terms: &[&str]) -> &'a str {
         let mut chs = self.chs.chars();
         let mut start = self.chs.len();
@@ -445,6 +1382,208 @@ impl<'a> Parser<'a> {
     }
 }
 
+// a footnote definition `[^key]: text`
+fn footnote_def(line: &str) -> Option<(&str, &str)> {
+    let (key, text) = line.strip_prefix("[^")?.split_once("]:")?;
+    if key.is_empty() || key.contains(char::is_whitespace) {
+        return None;
+    }
+    Some((key, text.trim()))
+}
+
+// `[[target#fragment|label]]` resolved after parsing all documents
+fn wiki_link(link: &str) -> Span<'_> {
+    let (link, label) = link.split_once('|').map_or((link, None), |(link, label)| (link, Some(label.trim())));
+    let (target, fragment) = link.split_once('#').map_or((link, None), |(target, fragment)| (target, Some(fragment.trim())));
+    let target = target.trim();
+    let label = label.unwrap_or(if target.is_empty() { fragment.unwrap_or("") } else { target });
+    WikiLink { target, fragment, label, url: None }
+}
+
+// a link definition `[key]: url "title"`
+fn link_def(line: &str) -> Option<(&str, &str, Option<&str>)> {
+    let (key, rest) = line.strip_prefix('[')?.split_once("]:")?;
+    if key.is_empty() || key.starts_with(['^', '@']) || key.contains('[') {
+        return None;
+    }
+    let (url, title) = split_title(rest);
+    // prose after the URL, not a single title, makes the line a paragraph
+    if title.is_none() && url != rest.trim() {
+        return None;
+    }
+    let url = url.strip_prefix('<').and_then(|url| url.strip_suffix('>')).unwrap_or(url);
+    if url.is_empty() || url.contains(char::is_whitespace) {
+        return None;
+    }
+    Some((key, url, title))
+}
+
+// a URL followed by an optional title in quotes or parentheses
+fn split_title(url: &str) -> (&str, Option<&str>) {
+    let url = url.trim();
+    let Some((url, title)) = url.split_once(char::is_whitespace) else {
+        return (url, None);
+    };
+    let title = title.trim();
+    let title = ["\"\"", "''", "()"].iter().find_map(|quotes| {
+        let (open, close) = quotes.split_at(1);
+        title.strip_prefix(open)?.strip_suffix(close).filter(|title| !title.contains(close))
+    });
+    (url, title)
+}
+
+// set the number of citations of each note in the references
+fn count_cites(blocks: &mut [Block], cites: &BTreeMap<usize, usize>) {
+    for block in blocks {
+        match block {
+            Ref(notes, _) => {
+                for (_, id, count) in notes {
+                    if let Some(&cites) = cites.get(id) {
+                        *count = cites;
+                    }
+                }
+            }
+            Env { content, .. } => count_cites(content, cites),
+            _ => {}
+        }
+    }
+}
+
+fn collect_notes<'a>(blocks: &[Block<'a>], notes: &mut BTreeMap<usize, Inline<'a>>) {
+    for block in blocks {
+        match block {
+            Ref(refs, _) => notes.extend(refs.iter().map(|(note, id, _)| (*id, note.clone()))),
+            Env { content, .. } => collect_notes(content, notes),
+            _ => {}
+        }
+    }
+}
+
+// spans of a header in the table of contents without notes and links
+fn strip_toc<'a>(spans: &[Span<'a>]) -> Vec<Span<'a>> {
+    let mut res = Vec::new();
+    for span in spans {
+        match span {
+            Cite { .. } | Citation { .. } => {}
+            Link { text, .. } => res.extend(strip_toc(&text.0)),
+            WikiLink { label, .. } => res.push(Text { text: (*label).into() }),
+            Bold { text } => res.push(Bold { text: Inline(strip_toc(&text.0)) }),
+            Ital { text } => res.push(Ital { text: Inline(strip_toc(&text.0)) }),
+            Strike { text } => res.push(Strike { text: Inline(strip_toc(&text.0)) }),
+            Mark { text } => res.push(Mark { text: Inline(strip_toc(&text.0)) }),
+            Underline { text } => res.push(Underline { text: Inline(strip_toc(&text.0)) }),
+            Sup { text } => res.push(Sup { text: Inline(strip_toc(&text.0)) }),
+            Sub { text } => res.push(Sub { text: Inline(strip_toc(&text.0)) }),
+            span => res.push(span.clone()),
+        }
+    }
+    res
+}
+
+fn field(record: &[String], col: usize) -> &str {
+    record.get(col).map_or("", |field| field.trim())
+}
+
+// cells consisting of `<` or `^` are merged into the cell on the left or above
+fn merge_cells<'a>(rows: Vec<(usize, Vec<Inline<'a>>)>, aligns: &[Option<Align>]) -> Vec<Vec<Cell<'a>>> {
+    let mut cells: Vec<Vec<Cell>> = Vec::new();
+    let mut grid: Vec<Vec<Option<(usize, usize)>>> = Vec::new();
+    for (r, (_, row)) in rows.into_iter().enumerate() {
+        cells.push(Vec::new());
+        grid.push(Vec::new());
+        for (c, text) in row.into_iter().enumerate() {
+            // the text may be split into several spans at `^`
+            let marker = if text.0.iter().all(|span| matches!(span, Text { .. })) { text.plain_text() } else { String::new() };
+            let marker = marker.trim();
+            let owner = match marker {
+                "<" if c > 0 => grid[r][c - 1],
+                "^" if r > 0 => grid[r - 1].get(c).copied().flatten(),
+                _ => None,
+            };
+            if let Some((i, j)) = owner {
+                let cell = &mut cells[i][j];
+                if marker == "<" && i == r {
+                    cell.colspan += 1;
+                } else if marker == "^" && cell.rowspan == r - i {
+                    cell.rowspan += 1;
+                }
+                grid[r].push(owner);
+            } else {
+                let align = aligns.get(c).copied().flatten();
+                grid[r].push(Some((r, cells[r].len())));
+                cells[r].push(Cell { text, colspan: 1, rowspan: 1, align });
+            }
+        }
+    }
+    cells
+}
+
+// lines selected by `L10-L40`, `L10` or the name of a region, with the number of the first line
+fn select_lines<'s>(src: &'s str, fragment: &str) -> Option<(usize, Vec<&'s str>)> {
+    let lines: Vec<&str> = src.lines().collect();
+    if fragment.is_empty() {
+        return Some((1, lines));
+    }
+
+    // `L<n>` or `L<n>-L<m>`, and otherwise a region even if its name starts with `L`
+    let line_number = |line: &str| line.strip_prefix('L').filter(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))?.parse::<usize>().ok();
+    let range = fragment.split_once('-').map_or((fragment, fragment), |range| range);
+    if let (Some(first), Some(last)) = (line_number(range.0), line_number(range.1)) {
+        if first == 0 || first > last || last > lines.len() {
+            return None;
+        }
+        return Some((first, lines[first - 1..last].to_vec()));
+    }
+
+    // a region between the marker comments `#region name` and `#endregion`
+    let region = |line: &str| line.split_once("#region").map(|(_, name)| name.trim().trim_end_matches("-->").trim().to_string());
+    let start = lines.iter().position(|line| region(line).as_deref() == Some(fragment))? + 1;
+    let mut depth = 0;
+    let mut selected = Vec::new();
+    for line in &lines[start..] {
+        if line.contains("#endregion") {
+            if depth == 0 {
+                return Some((start + 1, selected));
+            }
+            depth -= 1;
+        } else if region(line).is_some() {
+            depth += 1;
+        } else {
+            selected.push(*line);
+        }
+    }
+    None
+}
+
+// attributes of a code block such as `title="src/main.rs" linenos hl=3-5,9`
+fn parse_code_attrs(attrs: Attrs) -> Result<CodeAttrs, SyntaxError> {
+    let mut code_attrs = CodeAttrs { title: attrs.get("title"), ..CodeAttrs::default() };
+    if let Some(start) = attrs.get("linenos") {
+        code_attrs.linenos = Some(if start.is_empty() { 1 } else { start.parse().map_err(|_| Expect(&["linenos=<number>"]))? });
+    }
+    if let Some(lines) = attrs.get("hl") {
+        for range in lines.split(',') {
+            let (first, last) = range.split_once('-').unwrap_or((range, range));
+            let (Ok(first), Ok(last)) = (first.trim().parse(), last.trim().parse()) else {
+                return Err(Expect(&["hl=<line>-<line>,<line>"]));
+            };
+            code_attrs.hl_lines.push((first, last));
+        }
+    }
+    Ok(code_attrs)
+}
+
+// split trailing attributes `{...}` from a line
+fn split_attrs(line: &str) -> (&str, Attrs<'_>) {
+    let trimmed = line.trim_end();
+    if let Some(rest) = trimmed.strip_suffix('}') {
+        if let Some(start) = rest.rfind('{').filter(|&start| !rest[start + 1..].starts_with('@')) {
+            return (&line[..start], Attrs::parse(&rest[start + 1..]));
+        }
+    }
+    (line, Attrs::default())
+}
+
 #[tokio::main]
 async fn get_title(url: &str) -> String {
     let client = reqwest::Client::new();
@@ -0,0 +1,268 @@
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+
+use crate::codegen::gen_html;
+use crate::data::*;
+use crate::parser::{parse, Includes};
+use crate::template::read_template;
+use Block::*;
+use Span::*;
+
+pub struct Page<'a> {
+    pub src: PathBuf,
+    pub out: PathBuf,
+    pub doc: Document<'a>,
+}
+
+// convert every document under `src_dir` into the same place under `dest_dir`
+pub fn build_site(temp_path: &str, src_dir: &Path, dest_dir: &Path, options: &[(&str, &str)]) {
+    let mut files = Vec::new();
+    if find_documents(src_dir, &mut files).is_err() {
+        println!("could not read the source directory.");
+        return;
+    }
+
+    let mut sources = Vec::new();
+    for file in files {
+        let Ok(src) = fs::read_to_string(&file) else {
+            println!("could not open the source file \"{}\".", file.display());
+            return;
+        };
+        let includes = Includes::read(&file, &src);
+        sources.push((file, src, includes));
+    }
+
+    let mut pages = Vec::new();
+    for (file, src, includes) in &sources {
+        let doc = match parse(src, file, includes, options) {
+            Ok(doc) => doc,
+            Err(err) => {
+                println!("Syntax Error: {}: {err}", file.display());
+                return;
+            }
+        };
+        let out = file.strip_prefix(src_dir).unwrap_or(file).with_extension("html");
+        pages.push(Page { src: file.clone(), out, doc });
+    }
+    let broken = resolve_wiki_links(&mut pages);
+    warn_wiki_links(&pages, &broken);
+    let graph = link_graph(&mut pages);
+    set_backlinks(&mut pages, &graph);
+
+    let Ok(temp) = read_template(temp_path) else {
+        println!("could not open or read the template file.");
+        return;
+    };
+
+    for page in &pages {
+        let dest_path = dest_dir.join(&page.out);
+        let Ok(mut dest) = fs::create_dir_all(dest_path.parent().unwrap()).and_then(|_| File::create(&dest_path)) else {
+            println!("could not open or create the destination file \"{}\".", dest_path.display());
+            return;
+        };
+        let file = page.out.file_stem().unwrap().to_str().unwrap();
+        let Ok(_) = gen_html(&mut dest, file, &page.doc, &temp) else {
+            println!("could not write to the destination file \"{}\".", dest_path.display());
+            return;
+        };
+    }
+
+    let graph_path = dest_dir.join("graph.json");
+    let Ok(_) = File::create(&graph_path).and_then(|mut dest| write_graph(&mut dest, &pages, &graph)) else {
+        println!("could not write the link graph \"{}\".", graph_path.display());
+        return;
+    };
+}
+
+pub fn find_documents(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)?.map(|entry| entry.map(|entry| entry.path())).collect::<Result<_, _>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            find_documents(&path, files)?;
+        } else if path.extension().is_some_and(|ext| ext == "md") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+struct Entry {
+    out: PathBuf,
+    names: [String; 3],
+    ids: Vec<String>,
+}
+
+// set the URL of `[[target#fragment]]` from the file names, the titles and the header IDs of the pages,
+// and return the links that could not be resolved with the index of their page
+pub fn resolve_wiki_links(pages: &mut [Page]) -> Vec<(usize, String)> {
+    let index: Vec<Entry> = pages
+        .iter()
+        .map(|page| {
+            let stem = page.out.file_stem().map_or(String::new(), |stem| normalize(&stem.to_string_lossy()));
+            let path = normalize(&page.out.with_extension("").to_string_lossy());
+            let ids = page.doc.content.iter().filter_map(|block| if let Header { id, .. } = block { Some(id.clone()) } else { None }).collect();
+            Entry { out: page.out.clone(), names: [stem, path, normalize(&page.doc.title)], ids }
+        })
+        .collect();
+
+    let mut broken = Vec::new();
+    for (i, page) in pages.iter_mut().enumerate() {
+        let mut resolve = |span: &mut Span| -> Result<(), std::convert::Infallible> {
+            if let WikiLink { target, fragment, url, .. } = span {
+                *url = resolve(&index, i, target, *fragment);
+                if url.is_none() {
+                    let fragment = fragment.map_or(String::new(), |fragment| format!("#{fragment}"));
+                    broken.push((i, format!("[[{target}{fragment}]]")));
+                }
+            }
+            Ok(())
+        };
+        for block in &mut page.doc.content {
+            let _ = block.walk_spans_mut(&mut resolve);
+        }
+    }
+    broken
+}
+
+pub fn warn_wiki_links(pages: &[Page], broken: &[(usize, String)]) {
+    for (i, link) in broken {
+        println!("Warning: {}: wiki link \"{link}\" could not be resolved", pages[*i].src.display());
+    }
+}
+
+fn resolve(index: &[Entry], from: usize, target: &str, fragment: Option<&str>) -> Option<String> {
+    let to = if target.is_empty() {
+        from
+    } else {
+        let target = normalize(target);
+        index.iter().position(|entry| entry.names.contains(&target))?
+    };
+    let page = if to == from { String::new() } else { relative_url(&index[from].out, &index[to].out) };
+    let Some(fragment) = fragment else {
+        return Some(page);
+    };
+
+    // a header ID or the header ID with the same normalized form
+    let ids = &index[to].ids;
+    let id = ids.iter().find(|id| *id == fragment).or_else(|| ids.iter().find(|id| normalize(id) == normalize(fragment)))?;
+    Some(format!("{page}#{id}"))
+}
+
+// names compared ignoring case, with spaces and underscores as hyphens
+fn normalize(name: &str) -> String {
+    name.trim().to_lowercase().replace([' ', '_', '\\'], "-")
+}
+
+// the path of `to` relative to the directory of `from`
+pub fn relative_url(from: &Path, to: &Path) -> String {
+    let from: Vec<Component> = from.parent().map_or(Vec::new(), |dir| dir.components().collect());
+    let to: Vec<Component> = to.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+
+    let mut url: Vec<String> = vec!["..".to_string(); from.len() - common];
+    url.extend(to[common..].iter().map(|comp| comp.as_os_str().to_string_lossy().to_string()));
+    url.join("/")
+}
+
+// the other pages each page links to by wiki links or relative links
+fn link_graph(pages: &mut [Page]) -> Vec<BTreeSet<usize>> {
+    let outs: Vec<PathBuf> = pages.iter().map(|page| page.out.clone()).collect();
+    let mut graph = Vec::new();
+    for (i, page) in pages.iter_mut().enumerate() {
+        let mut targets = BTreeSet::new();
+        let mut collect = |span: &mut Span| -> Result<(), std::convert::Infallible> {
+            let url = match span {
+                WikiLink { url: Some(url), .. } => url.as_str(),
+                Link { url, .. } => url,
+                _ => return Ok(()),
+            };
+            targets.extend(link_target(&outs, i, url));
+            Ok(())
+        };
+        for block in &mut page.doc.content {
+            let _ = block.walk_spans_mut(&mut collect);
+        }
+        graph.push(targets);
+    }
+    graph
+}
+
+// the page a relative URL refers to; links to `.md` sources count as links to their output
+fn link_target(outs: &[PathBuf], from: usize, url: &str) -> Option<usize> {
+    let path = &url[..url.find(['?', '#']).unwrap_or(url.len())];
+    if path.is_empty() || path.starts_with('/') || path.contains(':') {
+        return None;
+    }
+    let mut target = outs[from].parent().map_or(PathBuf::new(), Path::to_path_buf);
+    for comp in path.split('/') {
+        match comp {
+            "" | "." => {}
+            ".." => {
+                target.pop();
+            }
+            comp => target.push(comp),
+        }
+    }
+    if target.extension().is_some_and(|ext| ext == "md") {
+        target.set_extension("html");
+    }
+    outs.iter().position(|out| *out == target).filter(|&to| to != from)
+}
+
+fn set_backlinks(pages: &mut [Page], graph: &[BTreeSet<usize>]) {
+    for (i, targets) in graph.iter().enumerate() {
+        for &j in targets {
+            let title = page_title(&pages[i]);
+            let url = relative_url(&pages[j].out, &pages[i].out);
+            pages[j].doc.backlinks.push((title, url));
+        }
+    }
+}
+
+// the title of the document, or the file name without a title
+fn page_title(page: &Page) -> String {
+    if page.doc.title.is_empty() {
+        page.out.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().to_string())
+    } else {
+        page.doc.title.clone()
+    }
+}
+
+// `{"nodes": [{"id", "title"}], "links": [{"source", "target"}]}` with the output paths as IDs
+fn write_graph(dest: &mut File, pages: &[Page], graph: &[BTreeSet<usize>]) -> io::Result<()> {
+    let ids: Vec<String> = pages.iter().map(|page| json_string(&relative_url(Path::new(""), &page.out))).collect();
+    let nodes: Vec<String> = pages
+        .iter()
+        .zip(&ids)
+        .map(|(page, id)| format!("    {{ \"id\": {id}, \"title\": {} }}", json_string(&page_title(page))))
+        .collect();
+    let links: Vec<String> = graph
+        .iter()
+        .enumerate()
+        .flat_map(|(i, targets)| targets.iter().map(move |&j| (i, j)))
+        .map(|(i, j)| format!("    {{ \"source\": {}, \"target\": {} }}", ids[i], ids[j]))
+        .collect();
+
+    writeln!(dest, "{{")?;
+    writeln!(dest, "  \"nodes\": [\n{}\n  ],", nodes.join(",\n"))?;
+    writeln!(dest, "  \"links\": [\n{}\n  ]", links.join(",\n"))?;
+    writeln!(dest, "}}")
+}
+
+fn json_string(text: &str) -> String {
+    let mut res = String::from('"');
+    for c in text.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            c if c.is_control() => res.push_str(&format!("\\u{:04x}", c as u32)),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
@@ -13,14 +13,10 @@ pub fn read_template(path: &str) -> Result<Vec<Elem>, io::Error> {
     let pattern = Regex::new("\\{[a-z]+\\}").unwrap();
 
     while reader.read_line(&mut line)? > 0 {
-        let mut text_iter = pattern.split(&line);
+        let text_iter = pattern.split(&line);
         let mut attr_iter = pattern.find_iter(&line);
-        loop {
-            if let Some(text) = text_iter.next() {
-                template.push(Str(text.to_string()));
-            } else {
-                break;
-            }
+        for text in text_iter {
+            template.push(Str(text.to_string()));
             if let Some(attr) = attr_iter.next() {
                 template.push(match attr.as_str() {
                     "{file}" => FileName,
@@ -33,6 +29,8 @@ pub fn read_template(path: &str) -> Result<Vec<Elem>, io::Error> {
                     "{second}" => Second,
                     "{toc}" => Toc(attr.start()),
                     "{content}" => Content(attr.start()),
+                    "{macros}" => Macros(attr.start()),
+                    "{backlinks}" => Backlinks(attr.start()),
                     _ => { println!("unknown attribute"); panic!(); },
                 });
             }
@@ -0,0 +1,159 @@
+use std::fs;
+use std::process::Command;
+
+// converts `src` with a template of only `{content}` and returns the output
+fn convert(name: &str, src: &str) -> String {
+    convert_with(name, src, &[])
+}
+
+// converts `src` next to the other `files` given by their relative paths
+fn convert_with(name: &str, src: &str, files: &[(&str, &str)]) -> String {
+    let dir = std::env::temp_dir().join(format!("notex-test-{name}-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for (path, content) in files {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+    let (temp, doc, dest) = (dir.join("template.html"), dir.join("doc.md"), dir.join("doc.html"));
+    fs::write(&temp, "{content}").unwrap();
+    fs::write(&doc, src).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_notex")).arg(&temp).arg(&doc).arg(&dest).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stdout));
+    let html = fs::read_to_string(&dest).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    html
+}
+
+#[test]
+fn table_spanning_cells() {
+    let html = convert("span", "| a | b | c |\n|:---|:---:|---:|\n| aaa | total | < |\n| ^ | 10 | 20 |\n");
+    assert!(html.contains("<td rowspan=\"2\" style=\"text-align: left\">"), "{html}");
+    assert!(html.contains("<td colspan=\"2\" style=\"text-align: center\">"), "{html}");
+    assert_eq!(html.matches("<td").count(), 4, "{html}");
+    assert!(!html.contains('^') && !html.contains("&lt;"), "{html}");
+}
+
+#[test]
+fn at_sign_link_without_bibliography() {
+    let html = convert("at-link", "By [@season1618](https://github.com/season1618).\n");
+    assert!(html.contains("<a href=\"https://github.com/season1618\">@season1618</a>"), "{html}");
+}
+
+#[test]
+fn exclamation_before_footnote() {
+    let html = convert("bang-note", "Wow![^a note] nice.\n\nSee ![alt\n\nlater](x.png).\n");
+    assert!(html.contains("Wow!<sup id=\"cite-1\">"), "{html}");
+    assert!(html.contains("a note"), "{html}");
+    assert!(!html.contains("<img"), "{html}");
+}
+
+#[test]
+fn autolink_ending_with_underscores() {
+    let html = convert("autolink", "See https://d.org/x.html#object.__init__ ok, and https://d.org/a_b_.\n");
+    assert!(html.contains("<a href=\"https://d.org/x.html#object.__init__\">https://d.org/x.html#object.__init__</a> ok"), "{html}");
+    assert!(html.contains("<a href=\"https://d.org/a_b_\">https://d.org/a_b_</a>."), "{html}");
+
+    let html = convert("autolink-in-link", "[see https://d.org/__init__](u)\n");
+    assert!(html.contains("<a href=\"u\">see https://d.org/__init__</a>"), "{html}");
+}
+
+#[test]
+fn prose_after_link_definition_syntax() {
+    let html = convert("link-def", "[Note]: see the appendix for details.\n\n[a]: https://a.org \"A\"\n[b]: <https://b.org> (B)\n\n[x][a] [y][b]\n");
+    assert!(html.contains("[Note]: see the appendix for details."), "{html}");
+    assert!(html.contains("<a href=\"https://a.org\" title=\"A\">x</a>"), "{html}");
+    assert!(html.contains("<a href=\"https://b.org\" title=\"B\">y</a>"), "{html}");
+}
+
+#[test]
+fn table_body_row_of_dashes() {
+    let html = convert("table-dashes", "| a | b |\n|:--|--:|\n| - | - |\n| 1 | 2 |\n---\n");
+    assert_eq!(html.matches("<table>").count(), 1, "{html}");
+    assert!(html.contains("<td style=\"text-align: left\"> - </td>"), "{html}");
+    assert!(html.contains("<td style=\"text-align: right\"> 2 </td>"), "{html}");
+}
+
+#[test]
+fn code_regions_named_like_line_ranges() {
+    let code = "fn a() {}\n// #region Loader\nfn load() {}\n// #endregion\n// #region Lexer\nfn lex() {}\n// #endregion\n";
+    let html = convert_with("regions", "@[code](lib.rs#Loader)\n\n@[code](lib.rs#Lexer)\n\n@[code](lib.rs#L1-L1)\n", &[("lib.rs", code)]);
+    assert!(html.contains("load") && html.contains("lex"), "{html}");
+    assert_eq!(html.matches("<pre").count(), 3, "{html}");
+}
+
+#[test]
+fn relative_urls_in_included_documents() {
+    let part = "![i](img.svg) [l](other.md#top) [d][] [e](https://e.org) [f](#frag)\n\n[d]: ../up.html\n";
+    let svg = "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"10\" height=\"20\"></svg>";
+    let html = convert_with("include-urls", "@[include](parts/a.md)\n", &[("parts/a.md", part), ("parts/img.svg", svg)]);
+    assert!(html.contains("src=\"parts/img.svg\"") && html.contains("width=\"10\" height=\"20\""), "{html}");
+    assert!(html.contains("href=\"parts/other.md#top\""), "{html}");
+    assert!(html.contains("href=\"up.html\""), "{html}");
+    assert!(html.contains("href=\"https://e.org\"") && html.contains("href=\"#frag\""), "{html}");
+}
+
+#[test]
+fn check_does_not_fetch_pages() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.set_nonblocking(true).unwrap();
+    let url = format!("http://{}/page", listener.local_addr().unwrap());
+    let dir = std::env::temp_dir().join(format!("notex-test-check-fetch-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let doc = dir.join("doc.md");
+    fs::write(&doc, format!("[]({url})\n\n@[]({url})\n")).unwrap();
+
+    // a requested page never answers, so the check is given a few seconds
+    let mut child = Command::new(env!("CARGO_BIN_EXE_notex")).arg("check").arg(&doc).stdout(std::process::Stdio::null()).spawn().unwrap();
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break Some(status);
+        }
+        if std::time::Instant::now() > deadline {
+            child.kill().unwrap();
+            break None;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    };
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(listener.accept().is_err(), "a page was requested");
+    assert!(status.is_some_and(|status| status.success()));
+}
+
+#[test]
+fn citation_line_and_email_in_bibliography() {
+    let bib = "% ask a@b.org for details\n@book{k1,\n  title = {T},\n  year = 2000\n}\n";
+    let html = convert_with("bib-email", "---\nbibliography: refs.bib\n---\n\nsee [@k1]\n", &[("refs.bib", bib)]);
+    assert!(html.contains("href=\"#bib-k1\""), "{html}");
+
+    let dir = std::env::temp_dir().join(format!("notex-test-bib-line-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("refs.bib"), bib).unwrap();
+    fs::write(dir.join("doc.md"), "---\nbibliography: refs.bib\n---\n\nsee\n[@k2]\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_notex")).arg("check").arg(dir.join("doc.md")).output().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("line 6: citation \"k2\" is not found"), "{stdout}");
+}
+
+#[test]
+fn line_of_footnote_in_included_document() {
+    let dir = std::env::temp_dir().join(format!("notex-test-note-line-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("template.html"), "{content}").unwrap();
+    fs::write(dir.join("part.md"), "part\n\n[^n]: see [x][nokey]\n").unwrap();
+    fs::write(dir.join("doc.md"), "a\n\n@[include](part.md)\n\nb\n\nc[^n]\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_notex")).arg(dir.join("template.html")).arg(dir.join("doc.md")).arg(dir.join("doc.html")).output().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Warning: line 3: link \"nokey\" is not defined"), "{stdout}");
+}
+
+#[test]
+fn math_not_converted_to_mathml() {
+    let html = convert("mathml-fallback", "---\nmath: mathml\n---\n\n$x^2$ and $\\foo$\n");
+    assert!(html.contains("<math><msup><mi>x</mi><mn>2</mn></msup></math>"), "{html}");
+    assert!(html.contains("\\(\\foo\\)"), "{html}");
+}